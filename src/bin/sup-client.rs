@@ -1,13 +1,17 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use log::{error, info};
-use std::io::Write;
+use std::{
+    io::{Read, Write},
+    process, thread,
+};
 use sup_rs::{
-    config::config::Config,
+    config::config::Sup,
     controller::{
-        client::Client,
-        command::{Command, Request},
+        client::{AttachWriter, Client, ResponseStream},
+        command::{Command, Request, Response, ResponseBody},
     },
 };
+use tokio::sync::mpsc;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -16,16 +20,27 @@ struct Args {
     #[arg(short, long)]
     config_path: String,
 
+    // format of printed response
+    #[arg(short, long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
     #[clap(subcommand)]
     subcommand: Command,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
 #[tokio::main]
 async fn main() {
     env_logger::Builder::from_default_env()
         .format_timestamp_secs()
         .format(|buf, record| {
-            writeln!(buf,
+            writeln!(
+                buf,
                 "{} - {} - {} - {}",
                 buf.timestamp(),
                 record.file().unwrap(),
@@ -36,17 +51,182 @@ async fn main() {
         .init();
 
     let args = Args::parse();
-    let cfg = match Config::new(&args.config_path) {
-        Ok(c) => c,
-        Err(e) => panic!("create config failed: {}", e.to_string()),
+    // rest of config is not read, client may run as another user and in
+    // another environment
+    let socket = match Sup::socket(&args.config_path) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("read socket from config failed: {}", e);
+            process::exit(1);
+        }
+    };
+    let cli = Client::new(socket);
+    if args.subcommand == Command::Attach {
+        return attach(&cli, args.output).await;
+    }
+    let streaming = matches!(args.subcommand, Command::Tail { .. } | Command::Events);
+    let mut stream = match cli.request_stream(Request::new(args.subcommand)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("request failed: {e}");
+            process::exit(1);
+        }
     };
-    let cli = Client::new(cfg.sup.socket);
-    match cli.request(Request::new(args.subcommand)).await {
-        Ok(resp) => {
-            info!("get resp: {resp}")
+    loop {
+        let resp = match stream.next().await {
+            Ok(Some(resp)) => resp,
+            Ok(None) => return,
+            Err(e) => {
+                error!("request failed: {e}");
+                process::exit(1);
+            }
+        };
+        info!("get resp: {:?}", resp);
+        print_response(&resp, args.output, streaming);
+        if resp.is_error() {
+            process::exit(1);
         }
+    }
+}
+
+// ctrl-]
+const DETACH_KEY: u8 = 0x1d;
+
+async fn attach(cli: &Client, output: Output) {
+    let (mut stream, mut writer) = match cli.attach().await {
+        Ok(attached) => attached,
         Err(e) => {
-            error!("request failed: {e}")
+            error!("request failed: {e}");
+            process::exit(1);
         }
+    };
+
+    // blocking stdin read lives on its own thread, it is left behind on exit
+    let (input_send, mut input) = mpsc::channel(16);
+    thread::spawn(move || {
+        let mut buf = [0; 1024];
+        loop {
+            match std::io::stdin().read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    if input_send.blocking_send(buf[..n].to_vec()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    eprintln!("attached, detach with ctrl-]");
+    let raw = RawTerminal::enable();
+    let failed = forward(&mut stream, &mut writer, &mut input, output).await;
+    drop(raw);
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// print program output and send terminal input until detached,
+/// return true if attach failed
+async fn forward(
+    stream: &mut ResponseStream,
+    writer: &mut AttachWriter,
+    input: &mut mpsc::Receiver<Vec<u8>>,
+    output: Output,
+) -> bool {
+    let mut detaching = false;
+    loop {
+        tokio::select! {
+            resp = stream.next() => {
+                let resp = match resp {
+                    Ok(Some(resp)) => resp,
+                    Ok(None) => return false,
+                    Err(e) => {
+                        error!("request failed: {e}");
+                        return true;
+                    }
+                };
+                info!("get resp: {:?}", resp);
+                print_response(&resp, output, true);
+                if resp.is_terminal() {
+                    return resp.is_error();
+                }
+            }
+            data = input.recv(), if !detaching => {
+                // stdin closed detaches as well
+                let data = data.unwrap_or_else(|| vec![DETACH_KEY]);
+                let (data, detach) = match data.iter().position(|b| *b == DETACH_KEY) {
+                    Some(i) => (&data[..i], true),
+                    None => (&data[..], false),
+                };
+                let mut res = Ok(());
+                if !data.is_empty() {
+                    res = writer.input(data).await;
+                }
+                if res.is_ok() && detach {
+                    detaching = true;
+                    res = writer.detach().await;
+                }
+                if let Err(e) = res {
+                    error!("request failed: {e}");
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// puts terminal in raw mode so keys are sent as typed, restored on drop
+struct RawTerminal {
+    orig: libc::termios,
+}
+
+impl RawTerminal {
+    fn enable() -> Option<Self> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut orig: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut orig) != 0 {
+                return None;
+            }
+            let mut raw = orig;
+            libc::cfmakeraw(&mut raw);
+            // keep translating newline of program output
+            raw.c_oflag = orig.c_oflag;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(Self { orig })
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.orig);
+        }
+    }
+}
+
+fn print_response(resp: &Response, output: Output, streaming: bool) {
+    match output {
+        Output::Text if resp.is_error() => eprintln!("{resp}"),
+        Output::Text => match resp.body() {
+            ResponseBody::Output { data } => {
+                print!("{data}");
+                let _ = std::io::stdout().flush();
+            }
+            ResponseBody::Event { .. } => println!("{resp}"),
+            // output frames are all a streaming command prints
+            _ if streaming => {}
+            _ => println!("{resp}"),
+        },
+        Output::Json => match serde_json::to_string(resp) {
+            Ok(s) => println!("{s}"),
+            Err(e) => error!("encode response failed: {e}"),
+        },
     }
 }
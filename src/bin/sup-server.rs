@@ -1,7 +1,6 @@
 use clap::Parser;
-use env_logger;
-use log::info;
-use std::io::Write;
+use log::{error, info};
+use std::{io::Write, process, sync::Arc};
 use sup_rs::{config::config::Config, controller::server::Server};
 
 #[derive(Parser, Debug)]
@@ -10,6 +9,10 @@ struct Args {
     // path to toml format config file
     #[arg(short, long)]
     config_path: String,
+
+    // check config, print every problem found in it and exit
+    #[arg(long)]
+    check_config: bool,
 }
 
 #[tokio::main]
@@ -30,10 +33,38 @@ async fn main() {
         .init();
 
     let args = Args::parse();
+    if args.check_config {
+        match Config::check(&args.config_path) {
+            Ok(_) => println!("config {} is valid", args.config_path),
+            Err(problems) => {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                println!(
+                    "config {} is invalid, problems found: {}",
+                    args.config_path,
+                    problems.len()
+                );
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     let cfg = match Config::new(&args.config_path) {
         Ok(c) => c,
-        Err(e) => panic!("create config failed: {}", e.to_string()),
+        Err(e) => {
+            error!("create config failed: {}", e);
+            process::exit(1);
+        }
     };
     info!("server start");
-    Server::new(cfg.sup.socket).await.unwrap().run().await;
+    let server = match Server::new(cfg).await {
+        Ok(server) => server,
+        Err(e) => {
+            error!("create server failed: {:#}", e);
+            process::exit(1);
+        }
+    };
+    Arc::new(server).run().await;
 }
@@ -1,49 +1,273 @@
-use std::{collections::HashMap, env, fmt::Display, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    ffi::CString,
+    fmt::Display,
+    fs, io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
+use toml::Value;
 
-use super::error;
+use super::{env_file, error, include, interpolate};
 
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    // files merged into this one, such as conf.d/*.toml relative to it
+    #[serde(default)]
+    pub include: Vec<String>,
     pub sup: Sup,
     pub program: Program,
+    // programs notified of events of program
+    #[serde(default)]
+    pub listener: Vec<Listener>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Sup {
     #[serde(default = "default_socket")]
     pub socket: String,
+    // http api is served only if configured
+    pub http: Option<Http>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Http {
+    // tcp address such as 127.0.0.1:9001
+    pub address: Option<String>,
+    // unix socket path, used instead of address if set
+    pub socket: Option<String>,
+    // requests must carry `Authorization: Bearer <token>` if set
+    pub token: Option<String>,
+    // requests must carry basic auth if both are set
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Program {
+    // name of program, file stem of process path by default
+    #[serde(default)]
+    pub name: String,
     pub process: Process,
     pub log: Log,
+    // program is probed while running once set
+    pub health: Option<Health>,
+    // program is running once ready instead of after startSeconds
+    pub readiness: Option<Readiness>,
+    // listening sockets opened by sup and passed to program
+    #[serde(default)]
+    pub socket: Vec<Socket>,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
-#[serde(rename_all(deserialize = "camelCase"))]
+#[serde(rename_all(deserialize = "camelCase"), deny_unknown_fields)]
 pub struct Process {
     pub path: String,
     pub args: Option<Vec<String>>,
     pub envs: Option<HashMap<String, String>>,
+    // dotenv file read at each start, envs take precedence over it,
+    // relative path is joined to work dir
+    pub env_file: Option<String>,
+    // environment of sup is not inherited if set
+    #[serde(default)]
+    pub clean_env: bool,
     #[serde(default = "default_work_dir")]
     pub work_dir: String,
     #[serde(default = "default_auto_start")]
     pub auto_start: bool,
     #[serde(rename = "startSeconds", default = "default_start_interval")]
     pub start_interval: u64,
+    #[serde(default = "default_start_retries")]
+    pub start_retries: u32,
     #[serde(default = "default_restart_strategy")]
     pub restart_strategy: ProcessRestartStrategy,
+    // how restart command replaces running program
+    #[serde(default = "default_restart_mode")]
+    pub restart_mode: RestartMode,
+    #[serde(default = "default_stop_signal")]
+    pub stop_signal: StopSignal,
+    #[serde(rename = "stopSeconds", default = "default_stop_interval")]
+    pub stop_interval: u64,
+    // keep a pipe to stdin of program so a terminal can be attached
+    #[serde(default = "default_stdin")]
+    pub stdin: bool,
+    // resident memory in bytes of program and its descendants
+    pub max_rss: Option<u64>,
+    // 100 for a full core
+    pub max_cpu_percent: Option<f64>,
+    // limit is breached once usage stays above it for grace seconds
+    #[serde(rename = "limitGraceSeconds", default = "default_limit_grace")]
+    pub limit_grace: u64,
+    #[serde(default = "default_limit_action")]
+    pub limit_action: LimitAction,
+    // set in program before it runs path, such as nofile = 65536
+    #[serde(default)]
+    pub rlimits: BTreeMap<RlimitResource, Rlimit>,
+    // program runs as user and group, primary group of user if group is
+    // not set, sup must run as root to switch
+    pub user: Option<String>,
+    pub group: Option<String>,
+    // such as 0o022, umask of sup is inherited if not set
+    pub umask: Option<u32>,
+    // program is placed in its own cgroup v2 if set, kill and stop then
+    // reach all its descendants
+    pub cgroup: Option<Cgroup>,
+    // namespaces and restrictions of program, sup without root sets them
+    // up in a user namespace
+    pub sandbox: Option<Sandbox>,
+    // program sends WATCHDOG=1 to NOTIFY_SOCKET at least this often once
+    // running, otherwise it is restarted
+    #[serde(rename = "watchdogSeconds")]
+    pub watchdog: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), deny_unknown_fields)]
+pub struct Cgroup {
+    // memory.max in bytes
+    pub memory_max: Option<u64>,
+    // cpu.max, 100 for a full core
+    pub cpu_max_percent: Option<f64>,
+    pub pids_max: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), deny_unknown_fields)]
+pub struct Sandbox {
+    // bind mounted read only in a private mount namespace
+    #[serde(default)]
+    pub read_only_paths: Vec<String>,
+    // new network namespace, only a loopback device which is down
+    #[serde(default)]
+    pub private_network: bool,
+    // program is chrooted to root, path and workDir are resolved in it
+    pub root: Option<String>,
+    #[serde(default)]
+    pub no_new_privs: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
 #[serde(rename_all(deserialize = "camelCase"))]
+pub struct Health {
+    #[serde(flatten)]
+    pub probe: Probe,
+    #[serde(rename = "intervalSeconds", default = "default_health_interval")]
+    pub interval: u64,
+    #[serde(rename = "timeoutSeconds", default = "default_health_timeout")]
+    pub timeout: u64,
+    // program is unhealthy after this many failed probes in a row
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    // probes are not run in this time after program is spawned
+    #[serde(rename = "startGraceSeconds", default)]
+    pub start_grace: u64,
+    // restart unhealthy program, otherwise it is only reported
+    #[serde(default)]
+    pub restart: bool,
+}
+
+// probe succeeds if command exits with 0, address accepts connection or
+// url answers with 2xx or 3xx
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Probe {
+    Exec { command: Vec<String> },
+    Tcp { address: String },
+    Http { url: String },
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Readiness {
+    #[serde(flatten)]
+    pub check: ReadyCheck,
+    // program not ready in time is killed and retried as failed to start
+    #[serde(rename = "timeoutSeconds", default = "default_ready_timeout")]
+    pub timeout: u64,
+    // interval of probes and checks of file
+    #[serde(rename = "intervalSeconds", default = "default_ready_interval")]
+    pub interval: u64,
+}
+
+// exec, tcp and http are probes as those of health
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ReadyCheck {
+    Exec { command: Vec<String> },
+    Tcp { address: String },
+    Http { url: String },
+    // file appears, relative path is joined to work dir
+    File { path: String },
+    // regex matches a line of stdout or stderr
+    Stdout { pattern: String },
+    // program sends READY=1 to NOTIFY_SOCKET as sd_notify does, braces
+    // let unknown keys be denied as those of other checks
+    Notify {},
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Socket {
+    // passed in LISTEN_FDNAMES, name of program by default
+    #[serde(default)]
+    pub name: String,
+    #[serde(flatten)]
+    pub bind: SocketBind,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SocketBind {
+    Tcp { address: String },
+    // relative path is joined to work dir, stale file is replaced
+    Unix { path: String },
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Listener {
+    // name of listener, file stem of path by default
+    #[serde(default)]
+    pub name: String,
+    // relative path is joined to work dir of program
+    pub path: String,
+    pub args: Option<Vec<String>>,
+    pub envs: Option<HashMap<String, String>>,
+    // kinds of events sent to listener such as exited and fatal, all
+    // events are sent if empty
+    #[serde(default)]
+    pub events: Vec<EventName>,
+}
+
+// kinds of events, named as kind in json of events
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventName {
+    Started,
+    Exited,
+    Backoff,
+    Fatal,
+    Rotated,
+    Reloaded,
+    LimitExceeded,
+    Unhealthy,
+    Healthy,
+    WatchdogTimeout,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all(deserialize = "camelCase"), deny_unknown_fields)]
 pub struct Log {
     // path is the unique identifier for process
     // rotater only handle single rotation for one path at the same time
     pub path: String,
+    // stderr is written to path too if stderr path is not set
+    pub stderr_path: Option<String>,
     #[serde(default = "default_max_size")]
     pub max_size: u64,
     #[serde(default = "default_max_days")]
@@ -81,63 +305,630 @@ pub enum ProcessRestartStrategy {
     AlwaysNot,
 }
 
+// start-first starts a new instance and stops the old one once the new one
+// is ready, both run meanwhile so they share sockets or use SO_REUSEPORT
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub enum RestartMode {
+    #[serde(rename = "stop-first")]
+    StopFirst,
+    #[serde(rename = "start-first")]
+    StartFirst,
+}
+
+// action taken when program breaches maxRss or maxCpuPercent, event is
+// emitted for both
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub enum LimitAction {
+    #[serde(rename = "restart")]
+    Restart,
+    #[serde(rename = "event")]
+    Event,
+}
+
+// resources of setrlimit, named as in ulimit of shells
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub enum RlimitResource {
+    As,
+    Core,
+    Cpu,
+    Data,
+    Fsize,
+    Locks,
+    Memlock,
+    Msgqueue,
+    Nice,
+    Nofile,
+    Nproc,
+    Rss,
+    Rtprio,
+    Rttime,
+    Sigpending,
+    Stack,
+}
+
+impl RlimitResource {
+    const ALL: [(Self, &'static str); 16] = [
+        (Self::As, "as"),
+        (Self::Core, "core"),
+        (Self::Cpu, "cpu"),
+        (Self::Data, "data"),
+        (Self::Fsize, "fsize"),
+        (Self::Locks, "locks"),
+        (Self::Memlock, "memlock"),
+        (Self::Msgqueue, "msgqueue"),
+        (Self::Nice, "nice"),
+        (Self::Nofile, "nofile"),
+        (Self::Nproc, "nproc"),
+        (Self::Rss, "rss"),
+        (Self::Rtprio, "rtprio"),
+        (Self::Rttime, "rttime"),
+        (Self::Sigpending, "sigpending"),
+        (Self::Stack, "stack"),
+    ];
+}
+
+// keys of toml tables are only deserialized as strings
+impl TryFrom<String> for RlimitResource {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(r, _)| *r)
+            .ok_or_else(|| format!("unknown rlimit {:?}", s))
+    }
+}
+
+impl Display for RlimitResource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = Self::ALL
+            .iter()
+            .find(|(r, _)| r == self)
+            .map(|(_, name)| *name)
+            .unwrap_or_default();
+        write!(f, "{}", name)
+    }
+}
+
+// a single value sets both soft and hard limit
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Rlimit {
+    Both(RlimitValue),
+    Pair {
+        soft: RlimitValue,
+        hard: RlimitValue,
+    },
+}
+
+impl Rlimit {
+    pub fn soft(&self) -> RlimitValue {
+        match self {
+            Self::Both(v) | Self::Pair { soft: v, .. } => *v,
+        }
+    }
+
+    pub fn hard(&self) -> RlimitValue {
+        match self {
+            Self::Both(v) | Self::Pair { hard: v, .. } => *v,
+        }
+    }
+}
+
+// number or "unlimited"
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, PartialOrd)]
+#[serde(try_from = "RawRlimitValue")]
+pub enum RlimitValue {
+    Limited(u64),
+    Unlimited,
+}
+
+impl Display for RlimitValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Limited(v) => write!(f, "{}", v),
+            Self::Unlimited => write!(f, "unlimited"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawRlimitValue {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<RawRlimitValue> for RlimitValue {
+    type Error = String;
+
+    fn try_from(v: RawRlimitValue) -> Result<Self, Self::Error> {
+        match v {
+            RawRlimitValue::Number(n) => Ok(Self::Limited(n)),
+            RawRlimitValue::Text(s) if s == "unlimited" || s == "infinity" => Ok(Self::Unlimited),
+            RawRlimitValue::Text(s) => Err(format!(
+                "invalid rlimit {:?}, expect number or \"unlimited\"",
+                s
+            )),
+        }
+    }
+}
+
+// signal sent to the program by stop, program is killed if it is still
+// alive after stopSeconds
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+pub enum StopSignal {
+    #[serde(rename = "TERM")]
+    Term,
+    #[serde(rename = "INT")]
+    Int,
+    #[serde(rename = "QUIT")]
+    Quit,
+    #[serde(rename = "HUP")]
+    Hup,
+    #[serde(rename = "KILL")]
+    Kill,
+    #[serde(rename = "USR1")]
+    Usr1,
+    #[serde(rename = "USR2")]
+    Usr2,
+}
+
+// seconds are at most a year, so instants and microseconds of watchdog do
+// not overflow
+const MAX_SECONDS: u64 = 365 * 24 * 3600;
+
+impl Sup {
+    /// socket of sup in config at path, for sup-client. only the keys it is
+    /// resolved from are interpolated, so variables and problems of program
+    /// which only matter to sup-server don't stop the client
+    pub fn socket(path: &str) -> Result<String, error::Error> {
+        let value = include::load_raw(path)?;
+        let get = |key: &str| -> Result<Option<String>, error::Error> {
+            let mut v = Some(&value);
+            for k in key.split('.') {
+                v = v.and_then(|v| v.get(k));
+            }
+            let mut v = match v {
+                Some(v @ Value::String(_)) => v.clone(),
+                Some(_) => {
+                    return Err(error::Error::FormatCheckError(format!(
+                        "{} must be a string",
+                        key
+                    )))
+                }
+                None => return Ok(None),
+            };
+            interpolate::interpolate_value(&mut v)
+                .map_err(|e| error::Error::FormatCheckError(format!("{}: {}", key, e)))?;
+            Ok(v.as_str().map(str::to_string))
+        };
+        let socket = get("sup.socket")?.unwrap_or_else(default_socket);
+        let work_dir = get("program.process.workDir")?.unwrap_or_else(default_work_dir);
+        Ok(resolve(Path::new(&work_dir), &socket))
+    }
+}
+
 impl Config {
+    /// config at path, fails with the only problem found or with all of
+    /// them as [`error::Error::Problems`]
     pub fn new(path: &str) -> Result<Self, error::Error> {
-        let sr = fs::read_to_string(path);
-        let mut s = String::new();
-        match sr {
-            Err(e) => {
-                return Err(error::Error::ReadFileError(format!(
-                    "read path {} failed: {}",
-                    path, e
-                )))
-            }
-            Ok(p) => s.push_str(p.as_str()),
+        Self::check(path).map_err(|mut problems| match problems.len() {
+            1 => problems.remove(0),
+            _ => error::Error::Problems(problems),
+        })
+    }
+
+    /// config at path or every problem found in it. toml is checked first,
+    /// its error comes alone as nothing else can be checked then
+    pub fn check(path: &str) -> Result<Self, Vec<error::Error>> {
+        let mut t = Self::parse(path).map_err(|e| vec![e])?;
+        let mut problems = t.resolve();
+        problems.extend(t.check_files());
+        match problems.is_empty() {
+            true => Ok(t),
+            false => Err(problems),
         }
+    }
+
+    fn parse(path: &str) -> Result<Self, error::Error> {
+        let (value, origins) = include::load(path)?;
+        value.try_into().map_err(|e| locate(&origins, e))
+    }
 
-        let mut t: Config = toml::from_str(s.as_str()).unwrap();
+    /// resolve relative paths and defaults, problems of values are returned
+    fn resolve(&mut self) -> Vec<error::Error> {
+        let mut problems = vec![];
+        let mut problem = |e: Option<error::Error>| problems.extend(e);
 
-        let work_dir_path = Path::new(&t.program.process.work_dir);
-        if !work_dir_path.is_absolute() {
-            return Err(error::Error::FormatCheckError(
+        let work_dir = PathBuf::from(&self.program.process.work_dir);
+        if !work_dir.is_absolute() {
+            problem(Some(error::Error::FormatCheckError(
                 "work directory must be absolute".to_string(),
+            )));
+        }
+
+        let process = &mut self.program.process;
+        process.path = resolve(&work_dir, &process.path);
+        if let Some(env_file) = &mut process.env_file {
+            *env_file = resolve(&work_dir, env_file);
+        }
+
+        problem(check_seconds(
+            "program.process.startSeconds",
+            process.start_interval,
+            0,
+        ));
+        problem(check_seconds(
+            "program.process.stopSeconds",
+            process.stop_interval,
+            0,
+        ));
+        problem(check_seconds(
+            "program.process.limitGraceSeconds",
+            process.limit_grace,
+            0,
+        ));
+        if let Some(watchdog) = process.watchdog {
+            problem(check_seconds(
+                "program.process.watchdogSeconds",
+                watchdog,
+                1,
             ));
         }
+        if let Some(max_rss) = process.max_rss {
+            problem(check_positive("program.process.maxRss", max_rss));
+        }
+        if let Some(max_cpu_percent) = process.max_cpu_percent {
+            problem(check_positive(
+                "program.process.maxCpuPercent",
+                max_cpu_percent,
+            ));
+        }
+
+        for (resource, limit) in &process.rlimits {
+            if limit.soft() > limit.hard() {
+                problem(Some(error::Error::OutOfRange {
+                    key: format!("program.process.rlimits.{}", resource),
+                    message: format!(
+                        "soft limit {} is above hard limit {}",
+                        limit.soft(),
+                        limit.hard()
+                    ),
+                }));
+            }
+        }
+
+        if let Some(umask) = process.umask {
+            if umask > 0o777 {
+                problem(Some(error::Error::OutOfRange {
+                    key: "program.process.umask".to_string(),
+                    message: format!("{:o} is above 777", umask),
+                }));
+            }
+        }
 
-        let path = Path::new(&t.program.process.path);
-        if !path.is_absolute() {
-            t.program.process.path = Path::join(work_dir_path, path)
-                .to_str()
-                .unwrap()
-                .to_string();
+        if let Some(cgroup) = &process.cgroup {
+            if let Some(memory_max) = cgroup.memory_max {
+                problem(check_positive(
+                    "program.process.cgroup.memoryMax",
+                    memory_max,
+                ));
+            }
+            if let Some(cpu_max_percent) = cgroup.cpu_max_percent {
+                problem(check_positive(
+                    "program.process.cgroup.cpuMaxPercent",
+                    cpu_max_percent,
+                ));
+            }
+            if let Some(pids_max) = cgroup.pids_max {
+                problem(check_positive("program.process.cgroup.pidsMax", pids_max));
+            }
+        }
+
+        if let Some(sandbox) = &process.sandbox {
+            let relative = sandbox
+                .read_only_paths
+                .iter()
+                .chain(&sandbox.root)
+                .filter(|p| !Path::new(p).is_absolute());
+            for p in relative {
+                problem(Some(error::Error::FormatCheckError(format!(
+                    "sandbox path {} must be absolute",
+                    p
+                ))));
+            }
         }
 
-        let socket_path = Path::new(&t.sup.socket);
-        if !socket_path.is_absolute() {
-            t.sup.socket = Path::join(work_dir_path, socket_path)
-                .to_str()
-                .unwrap()
-                .to_string();
+        if let Some(health) = &self.program.health {
+            if let Probe::Exec { command } = &health.probe {
+                if command.is_empty() {
+                    problem(Some(error::Error::FormatCheckError(
+                        "command of exec probe is empty".to_string(),
+                    )));
+                }
+            }
+            problem(check_seconds(
+                "program.health.intervalSeconds",
+                health.interval,
+                1,
+            ));
+            problem(check_seconds(
+                "program.health.timeoutSeconds",
+                health.timeout,
+                1,
+            ));
+            problem(check_seconds(
+                "program.health.startGraceSeconds",
+                health.start_grace,
+                0,
+            ));
+            problem(check_positive(
+                "program.health.failureThreshold",
+                health.failure_threshold,
+            ));
+        }
+
+        if let Some(readiness) = &mut self.program.readiness {
+            match &mut readiness.check {
+                ReadyCheck::Stdout { pattern } => {
+                    if let Err(e) = regex::Regex::new(pattern) {
+                        problem(Some(error::Error::FormatCheckError(format!(
+                            "readiness pattern is invalid: {}",
+                            e
+                        ))));
+                    }
+                }
+                ReadyCheck::File { path } => *path = resolve(&work_dir, path),
+                _ => {}
+            }
+            problem(check_seconds(
+                "program.readiness.timeoutSeconds",
+                readiness.timeout,
+                1,
+            ));
+            problem(check_seconds(
+                "program.readiness.intervalSeconds",
+                readiness.interval,
+                1,
+            ));
         }
 
-        let logp = Path::new(&t.program.log.path);
-        if !logp.is_absolute() {
-            t.program.log.path = Path::join(work_dir_path, logp)
-                .to_str()
-                .unwrap()
-                .to_string();
+        // tcp and http probes connect from sup, outside a private network
+        // of program. exec probes join it
+        if let Some(sandbox) = &self.program.process.sandbox {
+            let health = self
+                .program
+                .health
+                .as_ref()
+                .is_some_and(|h| !matches!(h.probe, Probe::Exec { .. }));
+            let readiness = self.program.readiness.as_ref().is_some_and(|r| {
+                matches!(r.check, ReadyCheck::Tcp { .. } | ReadyCheck::Http { .. })
+            });
+            for (key, network) in [("health", health), ("readiness", readiness)] {
+                if sandbox.private_network && network {
+                    problem(Some(error::Error::FormatCheckError(format!(
+                        "{} probe can not reach program in private network, use an exec probe",
+                        key
+                    ))));
+                }
+            }
+        }
+
+        if self.program.name.is_empty() {
+            self.program.name = Path::new(&self.program.process.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+        }
+
+        for socket in self.program.socket.iter_mut() {
+            if socket.name.is_empty() {
+                socket.name = self.program.name.clone();
+            }
+            if socket.name.contains(':') {
+                problem(Some(error::Error::FormatCheckError(format!(
+                    "socket name {} must not contain ':'",
+                    socket.name
+                ))));
+            }
+            if let SocketBind::Unix { path } = &mut socket.bind {
+                *path = resolve(&work_dir, path);
+            }
+        }
+
+        for listener in self.listener.iter_mut() {
+            listener.path = resolve(&work_dir, &listener.path);
+            if listener.name.is_empty() {
+                listener.name = Path::new(&listener.path)
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default();
+            }
+        }
+
+        self.sup.socket = resolve(&work_dir, &self.sup.socket);
+
+        if let Some(http) = &mut self.sup.http {
+            if http.address.is_none() && http.socket.is_none() {
+                problem(Some(error::Error::FormatCheckError(
+                    "sup.http needs address or socket".to_string(),
+                )));
+            }
+            if http.username.is_some() != http.password.is_some() {
+                problem(Some(error::Error::FormatCheckError(
+                    "sup.http needs both username and password".to_string(),
+                )));
+            }
+            if let Some(socket) = &mut http.socket {
+                *socket = resolve(&work_dir, socket);
+            }
+        }
+
+        let log = &mut self.program.log;
+        log.path = resolve(&work_dir, &log.path);
+        if let Some(stderr_path) = &mut log.stderr_path {
+            *stderr_path = resolve(&work_dir, stderr_path);
+        }
+        problem(check_positive("program.log.maxSize", log.max_size));
+
+        problems
+    }
+
+    /// problems of files config refers to, paths are resolved already
+    fn check_files(&self) -> Vec<error::Error> {
+        let mut problems = vec![];
+        let process = &self.program.process;
+
+        // path is looked up in root of sandbox by program
+        let root = process.sandbox.as_ref().and_then(|s| s.root.as_ref());
+        let path = match root {
+            Some(root) => Path::new(root).join(process.path.trim_start_matches('/')),
+            None => PathBuf::from(&process.path),
+        };
+        problems.extend(check_executable("program.process.path", &path));
+        for (i, listener) in self.listener.iter().enumerate() {
+            problems.extend(check_executable(
+                &format!("listener[{}].path", i),
+                Path::new(&listener.path),
+            ));
+        }
+
+        if let Some(env_file) = &process.env_file {
+            if let Err(e) = env_file::read(env_file) {
+                problems.push(error::Error::FormatCheckError(format!("{:#}", e)));
+            }
+        }
+
+        let log = &self.program.log;
+        problems.extend(check_writable("program.log.path", &log.path));
+        if let Some(stderr_path) = &log.stderr_path {
+            problems.extend(check_writable("program.log.stderrPath", stderr_path));
+        }
+        problems
+    }
+}
+
+/// path joined to dir if it is relative
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().to_string()
+}
+
+/// locate error of merged config in the file setting the key it is about,
+/// as toml only tells position when deserializing text
+fn locate(origins: &include::Origins, e: toml::de::Error) -> error::Error {
+    let message = e.to_string();
+    let key = message
+        .rsplit_once(" for key `")
+        .and_then(|(_, k)| k.split('`').next())
+        .unwrap_or("");
+    // unknown field is set by its own file, not by the one of its table
+    let origin = match message
+        .strip_prefix("unknown field `")
+        .and_then(|m| m.split('`').next())
+    {
+        Some(field) if key.is_empty() => field.to_string(),
+        Some(field) => format!("{}.{}", key, field),
+        None => key.to_string(),
+    };
+
+    let mut located = None;
+    for file in include::origin(origins, &origin) {
+        let text = match fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let ((line, column), exact) = match include::position(&text, key, &message) {
+            Some(position) => position,
+            None => continue,
+        };
+        let e = error::Error::ParseError {
+            file: file.clone(),
+            line: line + 1,
+            column: column + 1,
+            message: message.clone(),
+        };
+        if exact {
+            return e;
         }
+        located.get_or_insert(e);
+    }
+    located.unwrap_or(error::Error::FormatCheckError(message))
+}
+
+fn check_seconds(key: &str, seconds: u64, min: u64) -> Option<error::Error> {
+    if seconds < min {
+        return Some(error::Error::OutOfRange {
+            key: key.to_string(),
+            message: format!("{} is below {}", seconds, min),
+        });
+    }
+    if seconds > MAX_SECONDS {
+        return Some(error::Error::OutOfRange {
+            key: key.to_string(),
+            message: format!("{} is above {}", seconds, MAX_SECONDS),
+        });
+    }
+    None
+}
 
-        Ok(t)
+fn check_positive<T: PartialOrd + Default + Display>(key: &str, v: T) -> Option<error::Error> {
+    // NaN is not positive either
+    match v > T::default() {
+        true => None,
+        false => Some(error::Error::OutOfRange {
+            key: key.to_string(),
+            message: format!("{} is not positive", v),
+        }),
     }
 }
 
+fn check_executable(key: &str, path: &Path) -> Option<error::Error> {
+    let reason = match path.metadata() {
+        Ok(m) if !m.is_file() => "not a file".to_string(),
+        Ok(m) if m.permissions().mode() & 0o111 == 0 => "no execute permission".to_string(),
+        Ok(_) => return None,
+        Err(e) => e.to_string(),
+    };
+    Some(error::Error::NotExecutable {
+        key: key.to_string(),
+        path: path.display().to_string(),
+        reason,
+    })
+}
+
+/// directory of log file must exist, rotater only creates files
+fn check_writable(key: &str, path: &str) -> Option<error::Error> {
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new("/"));
+    let reason = match dir.metadata() {
+        Ok(m) if !m.is_dir() => "not a directory".to_string(),
+        Ok(_) => {
+            let c_dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+            match unsafe { libc::access(c_dir.as_ptr(), libc::W_OK) } {
+                0 => return None,
+                _ => io::Error::last_os_error().to_string(),
+            }
+        }
+        Err(e) => e.to_string(),
+    };
+    Some(error::Error::NotWritable {
+        key: key.to_string(),
+        dir: dir.display().to_string(),
+        reason,
+    })
+}
+
 fn default_socket() -> String {
     "./sup.sock".to_string()
 }
 
 fn default_work_dir() -> String {
-    env::current_dir().unwrap().to_str().unwrap().to_string()
+    // empty work dir is reported as not absolute
+    env::current_dir()
+        .map(|d| d.to_string_lossy().to_string())
+        .unwrap_or_default()
 }
 
 fn default_auto_start() -> bool {
@@ -148,10 +939,58 @@ fn default_start_interval() -> u64 {
     5
 }
 
+fn default_start_retries() -> u32 {
+    3
+}
+
 fn default_restart_strategy() -> ProcessRestartStrategy {
     ProcessRestartStrategy::OnFailure
 }
 
+fn default_restart_mode() -> RestartMode {
+    RestartMode::StopFirst
+}
+
+fn default_stop_signal() -> StopSignal {
+    StopSignal::Term
+}
+
+fn default_stop_interval() -> u64 {
+    10
+}
+
+fn default_stdin() -> bool {
+    false
+}
+
+fn default_limit_grace() -> u64 {
+    30
+}
+
+fn default_limit_action() -> LimitAction {
+    LimitAction::Restart
+}
+
+fn default_health_interval() -> u64 {
+    10
+}
+
+fn default_health_timeout() -> u64 {
+    3
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_ready_timeout() -> u64 {
+    60
+}
+
+fn default_ready_interval() -> u64 {
+    1
+}
+
 fn default_max_size() -> u64 {
     124217728
 }
@@ -175,19 +1014,53 @@ fn default_merge_compressed() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use error::Error;
 
     #[test]
     fn read_from_str() {
-        let s = "[sup]
+        let s = "include = [\"conf.d/*.toml\"]
+
+[sup]
 socket = \"/home/work/test/monitor/test-run/supd/run.sock\"
 
+[sup.http]
+address = \"127.0.0.1:9001\"
+token = \"secret\"
+
 [program]
+name = \"run\"
 [program.process]
 path = \"/home/work/test/monitor/test-run/conf/run.sh\"
 workDir = \"/home/work/test/monitor/test-run\"
+envFile = \"/home/work/test/monitor/test-run/conf/run.env\"
+cleanEnv = true
 startSeconds = 5
+startRetries = 5
 autoStart = true
 restartStrategy = \"on-failure\"
+restartMode = \"start-first\"
+stopSignal = \"INT\"
+stopSeconds = 3
+maxRss = 536870912
+limitGraceSeconds = 10
+limitAction = \"event\"
+user = \"work\"
+umask = 0o022
+watchdogSeconds = 30
+
+[program.process.rlimits]
+nofile = 65536
+nproc = { soft = 4096, hard = \"unlimited\" }
+core = 0
+
+[program.process.cgroup]
+memoryMax = 1073741824
+pidsMax = 512
+
+[program.process.sandbox]
+readOnlyPaths = [\"/home/work/test/monitor/test-run/conf\"]
+privateNetwork = true
+noNewPrivs = true
 
 [program.log]
 path = \"/home/work/test/monitor/test-run/log/run.log\"
@@ -195,71 +1068,505 @@ compress = false
 maxDays = 30
 maxBackups = 16
 maxSize = 128
+
+[program.health]
+type = \"http\"
+url = \"http://127.0.0.1:8080/health\"
+intervalSeconds = 5
+startGraceSeconds = 30
+restart = true
+
+[program.readiness]
+type = \"stdout\"
+pattern = \"listening on\"
+timeoutSeconds = 30
+
+[[program.socket]]
+name = \"http\"
+type = \"tcp\"
+address = \"0.0.0.0:8080\"
+
+[[program.socket]]
+type = \"unix\"
+path = \"/home/work/test/monitor/test-run/run/admin.sock\"
+
+[[listener]]
+path = \"/home/work/test/monitor/test-run/bin/alert\"
+events = [\"exited\", \"fatal\"]
 ";
         let t: Config = toml::from_str(s).unwrap();
         assert_eq!(
             t,
             Config {
+                include: vec!["conf.d/*.toml".to_string()],
                 sup: Sup {
-                    socket: "/home/work/test/monitor/test-run/supd/run.sock".to_string()
+                    socket: "/home/work/test/monitor/test-run/supd/run.sock".to_string(),
+                    http: Some(Http {
+                        address: Some("127.0.0.1:9001".to_string()),
+                        socket: None,
+                        token: Some("secret".to_string()),
+                        username: None,
+                        password: None,
+                    }),
                 },
                 program: Program {
+                    name: "run".to_string(),
                     process: Process {
                         path: "/home/work/test/monitor/test-run/conf/run.sh".to_string(),
                         args: None,
                         envs: None,
+                        env_file: Some("/home/work/test/monitor/test-run/conf/run.env".to_string()),
+                        clean_env: true,
                         work_dir: "/home/work/test/monitor/test-run".to_string(),
                         auto_start: true,
                         start_interval: 5,
+                        start_retries: 5,
                         restart_strategy: ProcessRestartStrategy::OnFailure,
+                        restart_mode: RestartMode::StartFirst,
+                        stop_signal: StopSignal::Int,
+                        stop_interval: 3,
+                        stdin: false,
+                        max_rss: Some(536870912),
+                        max_cpu_percent: None,
+                        limit_grace: 10,
+                        limit_action: LimitAction::Event,
+                        rlimits: BTreeMap::from([
+                            (
+                                RlimitResource::Nofile,
+                                Rlimit::Both(RlimitValue::Limited(65536))
+                            ),
+                            (
+                                RlimitResource::Nproc,
+                                Rlimit::Pair {
+                                    soft: RlimitValue::Limited(4096),
+                                    hard: RlimitValue::Unlimited,
+                                }
+                            ),
+                            (RlimitResource::Core, Rlimit::Both(RlimitValue::Limited(0))),
+                        ]),
+                        user: Some("work".to_string()),
+                        group: None,
+                        umask: Some(0o022),
+                        cgroup: Some(Cgroup {
+                            memory_max: Some(1073741824),
+                            cpu_max_percent: None,
+                            pids_max: Some(512),
+                        }),
+                        sandbox: Some(Sandbox {
+                            read_only_paths: vec![
+                                "/home/work/test/monitor/test-run/conf".to_string()
+                            ],
+                            private_network: true,
+                            root: None,
+                            no_new_privs: true,
+                        }),
+                        watchdog: Some(30),
                     },
                     log: Log {
                         path: "/home/work/test/monitor/test-run/log/run.log".to_string(),
+                        stderr_path: None,
                         max_size: 128,
                         max_days: 30,
                         max_backups: 16,
                         compress: false,
                         merge_compressed: false
-                    }
-                }
+                    },
+                    health: Some(Health {
+                        probe: Probe::Http {
+                            url: "http://127.0.0.1:8080/health".to_string(),
+                        },
+                        interval: 5,
+                        timeout: 3,
+                        failure_threshold: 3,
+                        start_grace: 30,
+                        restart: true,
+                    }),
+                    readiness: Some(Readiness {
+                        check: ReadyCheck::Stdout {
+                            pattern: "listening on".to_string(),
+                        },
+                        timeout: 30,
+                        interval: 1,
+                    }),
+                    socket: vec![
+                        Socket {
+                            name: "http".to_string(),
+                            bind: SocketBind::Tcp {
+                                address: "0.0.0.0:8080".to_string(),
+                            },
+                        },
+                        Socket {
+                            name: "".to_string(),
+                            bind: SocketBind::Unix {
+                                path: "/home/work/test/monitor/test-run/run/admin.sock".to_string(),
+                            },
+                        },
+                    ],
+                },
+                listener: vec![Listener {
+                    name: "".to_string(),
+                    path: "/home/work/test/monitor/test-run/bin/alert".to_string(),
+                    args: None,
+                    envs: None,
+                    events: vec![EventName::Exited, EventName::Fatal],
+                }],
             }
         );
     }
 
     #[test]
     fn read_from_file() {
-        let path = "../../test/config/config.toml";
-        match Config::new(path) {
-            Ok(c) => {
-                assert_eq!(
-                    c,
-                    Config {
-                        sup: Sup {
-                            socket: "/home/work/test/monitor/test-run/supd/run.sock".to_string()
-                        },
-                        program: Program {
-                            process: Process {
-                                path: "/home/work/test/monitor/test-run/conf/run.sh".to_string(),
-                                args: None,
-                                envs: None,
-                                work_dir: "/home/work/test/monitor/test-run".to_string(),
-                                auto_start: true,
-                                start_interval: 5,
-                                restart_strategy: ProcessRestartStrategy::OnFailure,
-                            },
-                            log: Log {
-                                path: "/home/work/test/monitor/test-run/log/run.log".to_string(),
-                                max_size: 128,
-                                max_days: 30,
-                                max_backups: 16,
-                                compress: false,
-                                merge_compressed: false,
-                            }
-                        }
-                    }
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/test/config/config.toml");
+        // files of program are not on this machine, nothing else is wrong
+        let problems = Config::check(path).unwrap_err();
+        let keys: Vec<&str> = problems
+            .iter()
+            .map(|e| match e {
+                Error::NotExecutable { key, .. } | Error::NotWritable { key, .. } => key.as_str(),
+                e => panic!("unexpected error {}", e),
+            })
+            .collect();
+        assert_eq!(keys, vec!["program.process.path", "program.log.path"]);
+
+        let mut c = Config::parse(path).unwrap();
+        assert!(c.resolve().is_empty());
+        assert_eq!(
+            c,
+            Config {
+                include: vec![],
+                sup: Sup {
+                    socket: "/home/work/test/monitor/test-run/supd/run.sock".to_string(),
+                    http: None,
+                },
+                program: Program {
+                    name: "run".to_string(),
+                    process: Process {
+                        path: "/home/work/test/monitor/test-run/conf/run.sh".to_string(),
+                        args: None,
+                        envs: None,
+                        env_file: None,
+                        clean_env: false,
+                        work_dir: "/home/work/test/monitor/test-run".to_string(),
+                        auto_start: true,
+                        start_interval: 5,
+                        start_retries: 3,
+                        restart_strategy: ProcessRestartStrategy::OnFailure,
+                        restart_mode: RestartMode::StopFirst,
+                        stop_signal: StopSignal::Term,
+                        stop_interval: 10,
+                        stdin: false,
+                        max_rss: None,
+                        max_cpu_percent: None,
+                        limit_grace: 30,
+                        limit_action: LimitAction::Restart,
+                        rlimits: BTreeMap::new(),
+                        user: None,
+                        group: None,
+                        umask: None,
+                        cgroup: None,
+                        sandbox: None,
+                        watchdog: None,
+                    },
+                    log: Log {
+                        path: "/home/work/test/monitor/test-run/log/run.log".to_string(),
+                        stderr_path: None,
+                        max_size: 128,
+                        max_days: 30,
+                        max_backups: 16,
+                        compress: false,
+                        merge_compressed: false,
+                    },
+                    health: None,
+                    readiness: None,
+                    socket: vec![],
+                },
+                listener: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn interpolate_in_new_test() {
+        let dir = env::temp_dir().join(format!("sup-config-env-{}", std::process::id()));
+        fs::create_dir_all(dir.join("log")).unwrap();
+        fs::write(dir.join("run.env"), "STAGE=staging\n").unwrap();
+        let path = dir.join("sup.toml");
+        let conf = |work_dir: &str| {
+            format!(
+                "[sup]
+[program.process]
+path = \"/bin/${{SUP_TEST_UNSET_PROGRAM:-sleep}}\"
+args = [\"${{SUP_TEST_UNSET_SECONDS:-10}}\"]
+workDir = \"{}\"
+envFile = \"run.env\"
+cleanEnv = true
+[program.log]
+path = \"log/run.log\"
+",
+                work_dir
+            )
+        };
+        fs::write(&path, conf(dir.to_str().unwrap())).unwrap();
+        let c = Config::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(c.program.name, "sleep");
+        assert_eq!(c.program.process.args, Some(vec!["10".to_string()]));
+        assert_eq!(
+            c.program.process.env_file,
+            Some(dir.join("run.env").to_str().unwrap().to_string())
+        );
+
+        fs::write(&path, conf("${SUP_TEST_UNSET_DIR}")).unwrap();
+        let e = Config::new(path.to_str().unwrap()).unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("variable SUP_TEST_UNSET_DIR is not set"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn socket_test() {
+        let dir = env::temp_dir().join(format!("sup-config-socket-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let path = dir.join("sup.toml");
+        fs::write(
+            &path,
+            "include = [\"conf.d/*.toml\"]
+[program.process]
+path = \"${SUP_TEST_UNSET_PROGRAM}\"
+workDir = \"/srv/run\"
+startSecond = 5
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("conf.d/sup.toml"),
+            "[sup]\nsocket = \"${SUP_TEST_UNSET_RUN_DIR:-run}/sup.sock\"\n",
+        )
+        .unwrap();
+        // unset variable and unknown key of program only matter to sup-server
+        assert_eq!(
+            Sup::socket(path.to_str().unwrap()).unwrap(),
+            "/srv/run/run/sup.sock"
+        );
+        assert!(Config::new(path.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_test() {
+        let dir = env::temp_dir().join(format!("sup-config-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sup.toml");
+        let write = |conf: &str| fs::write(&path, conf).unwrap();
+        let check = || Config::check(path.to_str().unwrap());
+
+        write(
+            "[sup]
+[program.process]
+path = \"/bin/sleep\"
+startSecond = 5
+[program.log]
+path = \"/tmp/run.log\"
+",
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::ParseError {
+                file,
+                line,
+                column,
+                message,
+            }] => {
+                assert_eq!(file, path.to_str().unwrap());
+                assert_eq!((*line, *column), (4, 1));
+                assert!(message.starts_with("unknown field `startSecond`"));
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+
+        write(
+            "[sup]
+[program.process]
+path = \"/bin/sleep\"
+startSeconds = \"5\"
+[program.log]
+path = \"/tmp/run.log\"
+",
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::ParseError { line, column, .. }] => {
+                assert_eq!((*line, *column), (4, 16))
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+
+        write(
+            "[sup]
+[program.process]
+path = \"run.sh\"
+workDir = \"WORK_DIR\"
+umask = 0o1022
+watchdogSeconds = 0
+[program.log]
+path = \"missing/run.log\"
+[program.health]
+type = \"tcp\"
+address = \"127.0.0.1:8080\"
+intervalSecond = 5
+"
+            .replace("WORK_DIR", dir.to_str().unwrap())
+            .as_str(),
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::ParseError {
+                line,
+                column,
+                message,
+                ..
+            }] => {
+                assert_eq!((*line, *column), (12, 1));
+                assert!(
+                    message.starts_with("unknown field `intervalSecond`"),
+                    "{message}"
+                );
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+
+        // error is located in the included file setting the key
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(
+            dir.join("conf.d/health.toml"),
+            "[program.health]
+type = \"tcp\"
+address = \"127.0.0.1:8080\"
+timeoutSeconds = \"5\"
+",
+        )
+        .unwrap();
+        write(
+            "include = [\"conf.d/*.toml\"]
+[sup]
+[program.process]
+path = \"/bin/sleep\"
+[program.log]
+path = \"/tmp/run.log\"
+",
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::ParseError {
+                file, line, column, ..
+            }] => {
+                assert_eq!(file, dir.join("conf.d/health.toml").to_str().unwrap());
+                assert_eq!((*line, *column), (4, 18));
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+        fs::remove_dir_all(dir.join("conf.d")).unwrap();
+
+        write(
+            "[sup]
+[program.process]
+path = \"run.sh\"
+workDir = \"WORK_DIR\"
+umask = 0o1022
+watchdogSeconds = 0
+[program.log]
+path = \"missing/run.log\"
+[program.health]
+type = \"tcp\"
+address = \"127.0.0.1:8080\"
+intervalSeconds = 0
+"
+            .replace("WORK_DIR", dir.to_str().unwrap())
+            .as_str(),
+        );
+        let problems = check().unwrap_err();
+        let keys: Vec<&str> = problems
+            .iter()
+            .map(|e| match e {
+                Error::OutOfRange { key, .. }
+                | Error::NotExecutable { key, .. }
+                | Error::NotWritable { key, .. } => key.as_str(),
+                e => panic!("unexpected error {}", e),
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "program.process.watchdogSeconds",
+                "program.process.umask",
+                "program.health.intervalSeconds",
+                "program.process.path",
+                "program.log.path",
+            ]
+        );
+        assert!(matches!(
+            Config::new(path.to_str().unwrap()),
+            Err(Error::Problems(problems)) if problems.len() == 5
+        ));
+
+        write(
+            "[sup]
+[program.process]
+path = \"/bin/sleep\"
+[program.log]
+path = \"/tmp/run.log\"
+[[listener]]
+path = \"/bin/true\"
+events = [\"exited\", \"fatl\"]
+",
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::ParseError {
+                line,
+                column,
+                message,
+                ..
+            }] => {
+                assert_eq!((*line, *column), (8, 21));
+                assert!(message.starts_with("unknown variant `fatl`"), "{message}");
+            }
+            e => panic!("unexpected errors {:?}", e),
+        }
+
+        write(
+            "[sup]
+[program.process]
+path = \"/bin/sleep\"
+[program.process.sandbox]
+privateNetwork = true
+[program.log]
+path = \"/tmp/run.log\"
+[program.health]
+type = \"tcp\"
+address = \"127.0.0.1:8080\"
+",
+        );
+        match check().unwrap_err().as_slice() {
+            [Error::FormatCheckError(message)] => {
+                assert!(
+                    message.starts_with("health probe can not reach"),
+                    "{message}"
                 )
             }
-            Err(_) => {}
+            e => panic!("unexpected errors {:?}", e),
         }
+
+        fs::write(dir.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::create_dir_all(dir.join("missing")).unwrap();
+        write(
+            "[sup]
+[program.process]
+path = \"run.sh\"
+workDir = \"WORK_DIR\"
+[program.log]
+path = \"missing/run.log\"
+"
+            .replace("WORK_DIR", dir.to_str().unwrap())
+            .as_str(),
+        );
+        assert_eq!(check().unwrap().program.name, "run");
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,92 @@
+use std::fs;
+
+use anyhow::{anyhow, Context, Result};
+
+/// env file in dotenv format, one `KEY=VALUE` per line:
+///
+/// - blank lines and lines starting with `#` are skipped
+/// - `export ` before key is ignored
+/// - value in single quotes is taken as is, value in double quotes has
+///   `\n`, `\"` and `\\` escaped, unquoted value is trimmed
+pub fn read(path: &str) -> Result<Vec<(String, String)>> {
+    let s = fs::read_to_string(path).context(format!("read env file {} failed", path))?;
+    parse(&s).context(format!("env file {} is invalid", path))
+}
+
+fn parse(s: &str) -> Result<Vec<(String, String)>> {
+    let mut envs = vec![];
+    for (i, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected KEY=VALUE", i + 1))?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(anyhow!("line {}: invalid key {:?}", i + 1, key));
+        }
+        let value =
+            unquote(value.trim()).ok_or_else(|| anyhow!("line {}: unclosed quote", i + 1))?;
+        envs.push((key.to_string(), value));
+    }
+    Ok(envs)
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let quote = match value.chars().next() {
+        Some(q @ ('\'' | '"')) => q,
+        _ => return Some(value.to_string()),
+    };
+    let inner = value[1..].strip_suffix(quote)?;
+    if quote == '\'' {
+        return Some(inner.to_string());
+    }
+    let mut unescaped = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => unescaped.push('\n'),
+            ('\\', Some(e @ ('"' | '\\'))) => unescaped.push(e),
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env_file_test() {
+        let envs = parse(
+            "# database
+export DB_HOST = db.internal
+DB_PASS='p@ss word'
+GREETING=\"hello\\n\\\"world\\\"\"
+EMPTY=
+",
+        )
+        .unwrap();
+        assert_eq!(
+            envs,
+            vec![
+                ("DB_HOST".to_string(), "db.internal".to_string()),
+                ("DB_PASS".to_string(), "p@ss word".to_string()),
+                ("GREETING".to_string(), "hello\n\"world\"".to_string()),
+                ("EMPTY".to_string(), "".to_string()),
+            ]
+        );
+
+        let e = parse("A=1\nB\n").unwrap_err();
+        assert_eq!(e.to_string(), "line 2: expected KEY=VALUE");
+        assert!(parse("A='1\n").is_err());
+    }
+}
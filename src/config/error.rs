@@ -7,4 +7,36 @@ pub enum Error {
     ReadFileError(String),
     #[error("config format invalid failed: [{0}]")]
     FormatCheckError(String),
+    // line and column start from 1
+    #[error("parse config failed: [{file}:{line}:{column}: {message}]")]
+    ParseError {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("value out of range: [{key}: {message}]")]
+    OutOfRange { key: String, message: String },
+    #[error("path is not executable: [{key} {path}: {reason}]")]
+    NotExecutable {
+        key: String,
+        path: String,
+        reason: String,
+    },
+    #[error("directory is not writable: [{key} {dir}: {reason}]")]
+    NotWritable {
+        key: String,
+        dir: String,
+        reason: String,
+    },
+    #[error("config has {} problems: [{}]", .0.len(), join(.0))]
+    Problems(Vec<Error>),
+}
+
+fn join(problems: &[Error]) -> String {
+    problems
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
 }
@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use toml::{value::Table, Value};
+
+use super::{error::Error, interpolate::interpolate_value};
+
+const INCLUDE_KEY: &str = "include";
+
+/// files which set each key of merged config, several for arrays of tables
+/// they all append to
+pub type Origins = HashMap<String, Vec<String>>;
+
+/// merged value of main file at path and files matching its `include`
+/// patterns, main file first, then matches of each pattern sorted by path,
+/// along with the files setting each key
+pub fn load(path: &str) -> Result<(Value, Origins), Error> {
+    load_files(path, true)
+}
+
+/// merged value as load returns it but with `${VAR}` left in strings, for
+/// readers that interpolate only the keys they use
+pub fn load_raw(path: &str) -> Result<Value, Error> {
+    Ok(load_files(path, false)?.0)
+}
+
+fn load_files(path: &str, interpolate: bool) -> Result<(Value, Origins), Error> {
+    let mut merged = read(Path::new(path), interpolate)?;
+    let mut origins = Origins::new();
+    for key in merged.keys() {
+        origins.insert(key.clone(), vec![path.to_string()]);
+    }
+    let patterns = match merged.get(INCLUDE_KEY) {
+        Some(Value::Array(patterns)) => patterns.clone(),
+        Some(_) => {
+            return Err(Error::FormatCheckError(format!(
+                "key {} in {} must be an array of paths",
+                INCLUDE_KEY, path
+            )))
+        }
+        None => return Ok((Value::Table(merged), origins)),
+    };
+
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new("."));
+    let mut files = vec![PathBuf::from(path)];
+    for pattern in patterns {
+        let pattern = pattern.as_str().ok_or_else(|| {
+            Error::FormatCheckError(format!("include pattern {} must be a string", pattern))
+        })?;
+        for file in expand(dir, pattern) {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+
+    for file in &files[1..] {
+        let table = read(file, interpolate)?;
+        let name = file.display().to_string();
+        if table.contains_key(INCLUDE_KEY) {
+            return Err(Error::FormatCheckError(format!(
+                "key {} in {} is only allowed in main file {}",
+                INCLUDE_KEY, name, path
+            )));
+        }
+        merge(&mut merged, table, "", &name, &mut origins)?;
+    }
+    Ok((Value::Table(merged), origins))
+}
+
+fn read(path: &Path, interpolate: bool) -> Result<Table, Error> {
+    let s = fs::read_to_string(path)
+        .map_err(|e| Error::ReadFileError(format!("read path {:?} failed: {}", path, e)))?;
+    let mut value: Value = toml::from_str(&s).map_err(|e| parse_error(path, &e))?;
+    if interpolate {
+        interpolate_value(&mut value)
+            .map_err(|e| Error::FormatCheckError(format!("{}: {}", path.display(), e)))?;
+    }
+    match value {
+        Value::Table(table) => Ok(table),
+        _ => Err(Error::FormatCheckError(format!(
+            "{} is not a table",
+            path.display()
+        ))),
+    }
+}
+
+/// error of toml at its position in text of file
+fn parse_error(path: &Path, e: &toml::de::Error) -> Error {
+    let message = e.to_string();
+    match e.line_col() {
+        Some((line, column)) => Error::ParseError {
+            file: path.display().to_string(),
+            line: line + 1,
+            column: column + 1,
+            message: strip_position(&message).to_string(),
+        },
+        None => Error::FormatCheckError(format!("{}: {}", path.display(), message)),
+    }
+}
+
+/// position in text of a file setting key of what an error of merged config
+/// at key is about: the unknown field or variant named by message, or else
+/// the value of key or header of its table. exact is false if only a table
+/// containing key is found, as for keys of inline tables
+pub fn position(text: &str, key: &str, message: &str) -> Option<((usize, usize), bool)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let (definitions, exact) = match key.is_empty() {
+        true => (vec![(0, 0)], true),
+        false => definitions(&lines, key),
+    };
+    let field = message
+        .strip_prefix("unknown field `")
+        .and_then(|m| m.split('`').next());
+    let variant = message
+        .strip_prefix("unknown variant `")
+        .and_then(|m| m.split('`').next());
+    if field.is_none() && variant.is_none() {
+        return definitions.first().map(|p| (*p, exact));
+    }
+    for &(line, _) in &definitions {
+        // rest of the table, or of the value starting at line
+        let found = lines
+            .iter()
+            .enumerate()
+            .skip(line)
+            .take_while(|(i, l)| *i == line || !l.trim_start().starts_with('['))
+            .find_map(|(i, l)| {
+                let trimmed = l.trim_start();
+                if let Some(field) = field {
+                    let rest = trimmed.strip_prefix(field)?.trim_start();
+                    return rest.starts_with('=').then(|| (i, l.len() - trimmed.len()));
+                }
+                let variant = variant?;
+                [format!("\"{}\"", variant), format!("'{}'", variant)]
+                    .iter()
+                    .find_map(|q| l.find(q.as_str()))
+                    .map(|c| (i, c))
+            });
+        if let Some(p) = found {
+            return Some((p, true));
+        }
+    }
+    definitions.first().map(|p| (*p, false))
+}
+
+/// lines defining the longest prefix of key, at the start of table headers
+/// or at values of keys, and whether the whole key is defined
+fn definitions(lines: &[&str], key: &str) -> (Vec<(usize, usize)>, bool) {
+    let key = split_key(key);
+    let mut table = vec![];
+    let mut longest = 0;
+    let mut found = vec![];
+    for (i, l) in lines.iter().enumerate() {
+        let trimmed = l.trim_start();
+        let (path, column) = if trimmed.starts_with('[') {
+            table = split_key(
+                trimmed
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or(""),
+            );
+            (table.clone(), l.len() - trimmed.len())
+        } else {
+            match trimmed.split_once('=') {
+                Some((k, v)) if !trimmed.starts_with('#') => {
+                    let path = table.iter().cloned().chain(split_key(k)).collect();
+                    (path, l.len() - v.trim_start().len())
+                }
+                _ => continue,
+            }
+        };
+        if path.is_empty() || !key.starts_with(&path) || path.len() < longest {
+            continue;
+        }
+        if path.len() > longest {
+            longest = path.len();
+            found.clear();
+        }
+        found.push((i, column));
+    }
+    (found, longest == key.len())
+}
+
+/// parts of a dotted key, quotes around them are dropped
+fn split_key(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|k| k.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// message of toml error without ` at line x column y` at its end
+fn strip_position(message: &str) -> &str {
+    match message.rfind(" at line ") {
+        Some(i) => &message[..i],
+        None => message,
+    }
+}
+
+/// merge table read from file into merged key by key, arrays of tables such
+/// as listener are appended and any other key set in both is an error.
+/// origins tell which file set each key
+fn merge(
+    merged: &mut Table,
+    table: Table,
+    prefix: &str,
+    file: &str,
+    origins: &mut Origins,
+) -> Result<(), Error> {
+    for (k, v) in table {
+        let key = match prefix {
+            "" => k.clone(),
+            _ => format!("{}.{}", prefix, k),
+        };
+        let existing = match merged.get_mut(&k) {
+            Some(existing) => existing,
+            None => {
+                origins.insert(key, vec![file.to_string()]);
+                merged.insert(k, v);
+                continue;
+            }
+        };
+        let mut files = origin(origins, &key).to_vec();
+        match (existing, v) {
+            (Value::Table(existing), Value::Table(v)) => merge(existing, v, &key, file, origins)?,
+            (Value::Array(existing), Value::Array(v))
+                if existing.iter().chain(&v).all(Value::is_table) =>
+            {
+                existing.extend(v);
+                files.push(file.to_string());
+                origins.insert(key, files);
+            }
+            _ => {
+                return Err(Error::FormatCheckError(format!(
+                    "key {} in {} is already set in {}",
+                    key,
+                    file,
+                    files.first().map_or("unknown file", String::as_str)
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// files which set key or the closest table containing it
+pub fn origin<'a>(origins: &'a Origins, key: &str) -> &'a [String] {
+    let mut key = key;
+    loop {
+        if let Some(files) = origins.get(key) {
+            return files;
+        }
+        match key.rsplit_once('.') {
+            Some((parent, _)) => key = parent,
+            None => return &[],
+        }
+    }
+}
+
+/// expand `*` and `?` in pattern relative to dir, matches are sorted and
+/// a pattern without them is returned as is
+fn expand(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let pattern = dir.join(pattern);
+    let mut paths = vec![PathBuf::new()];
+    let mut wildcard = false;
+    for component in pattern.components() {
+        let c = match component {
+            Component::Normal(c) if c.as_bytes().iter().any(|b| b"*?".contains(b)) => c,
+            c => {
+                paths.iter_mut().for_each(|p| p.push(c));
+                continue;
+            }
+        };
+        wildcard = true;
+        let mut next = vec![];
+        for path in &paths {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                // hidden files are only matched by a pattern starting with .
+                if name.as_bytes().starts_with(b".") && !c.as_bytes().starts_with(b".") {
+                    continue;
+                }
+                if matches(c.as_bytes(), name.as_bytes()) {
+                    next.push(path.join(name));
+                }
+            }
+        }
+        next.sort();
+        paths = next;
+    }
+    if wildcard {
+        paths.retain(|p| p.is_file());
+    }
+    paths
+}
+
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', p)), _) => {
+            matches(p, name) || (!name.is_empty() && matches(pattern, &name[1..]))
+        }
+        (Some((b'?', p)), Some((_, n))) => matches(p, n),
+        (Some((c, p)), Some((d, n))) if c == d => matches(p, n),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_test() {
+        let dir = std::env::temp_dir().join(format!("sup-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let main = dir.join("sup.toml");
+        fs::write(
+            &main,
+            "include = [\"conf.d/*.toml\"]
+[sup]
+socket = \"/run/sup.sock\"
+[[listener]]
+path = \"/bin/alert\"
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("conf.d/20-listener.toml"),
+            "[[listener]]\npath = \"/bin/audit\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("conf.d/10-program.toml"),
+            "[sup.http]\naddress = \"127.0.0.1:9001\"\n[program]\nname = \"run\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("conf.d/.hidden.toml"), "[sup]\nsocket = \"x\"\n").unwrap();
+        fs::write(dir.join("conf.d/readme.md"), "").unwrap();
+
+        let (merged, origins) = load(main.to_str().unwrap()).unwrap();
+        let file = |name: &str| dir.join(name).display().to_string();
+        assert_eq!(
+            origin(&origins, "listener.path"),
+            [file("sup.toml"), file("conf.d/20-listener.toml")]
+        );
+        assert_eq!(
+            origin(&origins, "sup.http.address"),
+            [file("conf.d/10-program.toml")]
+        );
+        assert_eq!(origin(&origins, "sup.socket"), [file("sup.toml")]);
+        let listeners: Vec<_> = merged["listener"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(listeners, vec!["/bin/alert", "/bin/audit"]);
+        assert_eq!(merged["sup"]["socket"].as_str(), Some("/run/sup.sock"));
+        assert_eq!(
+            merged["sup"]["http"]["address"].as_str(),
+            Some("127.0.0.1:9001")
+        );
+        assert_eq!(merged["program"]["name"].as_str(), Some("run"));
+
+        // program is merged key by key as other tables
+        let duplicate = dir.join("conf.d/30-program.toml");
+        fs::write(&duplicate, "[program.health]\ntype = \"tcp\"\n").unwrap();
+        let (merged, _) = load(main.to_str().unwrap()).unwrap();
+        assert_eq!(merged["program"]["name"].as_str(), Some("run"));
+        assert_eq!(merged["program"]["health"]["type"].as_str(), Some("tcp"));
+
+        fs::write(&duplicate, "[program]\nname = \"web\"\n").unwrap();
+        let e = load(main.to_str().unwrap()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "config format invalid failed: [key program.name in {} is already set in {}]",
+                duplicate.display(),
+                dir.join("conf.d/10-program.toml").display()
+            )
+        );
+
+        fs::write(&duplicate, "[sup]\nsocket = \"/run/other.sock\"\n").unwrap();
+        let e = load(main.to_str().unwrap()).unwrap_err();
+        assert!(e.to_string().contains(&format!(
+            "key sup.socket in {} is already set in {}",
+            duplicate.display(),
+            main.display()
+        )));
+
+        fs::write(&duplicate, "[sup\n").unwrap();
+        match load(main.to_str().unwrap()).unwrap_err() {
+            Error::ParseError {
+                file, line, column, ..
+            } => assert_eq!(
+                (file, line, column),
+                (duplicate.display().to_string(), 1, 5)
+            ),
+            e => panic!("unexpected error {}", e),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn matches_test() {
+        assert!(matches(b"*.toml", b"a.toml"));
+        assert!(matches(b"*.toml", b".toml"));
+        assert!(matches(b"?-*", b"1-web"));
+        assert!(!matches(b"*.toml", b"a.toml.bak"));
+        assert!(!matches(b"?", b""));
+    }
+}
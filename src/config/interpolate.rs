@@ -0,0 +1,98 @@
+use std::env;
+
+use toml::Value;
+
+/// `${VAR}` and `${VAR:-default}` in string values of config are replaced
+/// by environment of sup, default is taken if VAR is unset or empty.
+/// `$${` is a literal `${`.
+///
+/// interpolate every string in value, error tells which variable is missing
+pub fn interpolate_value(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(s) => *s = interpolate(s, |name| env::var(name).ok())?,
+        Value::Array(values) => {
+            for v in values {
+                interpolate_value(v)?;
+            }
+        }
+        Value::Table(table) => {
+            for (_, v) in table.iter_mut() {
+                interpolate_value(v)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = r;
+            continue;
+        }
+        let body = match rest.strip_prefix("${") {
+            Some(body) => body,
+            None => {
+                out.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let end = body
+            .find('}')
+            .ok_or_else(|| format!("unclosed ${{ in {:?}", s))?;
+        let (name, default) = match body[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&body[..end], None),
+        };
+        if name.is_empty() {
+            return Err(format!("empty variable name in {:?}", s));
+        }
+        match (lookup(name), default) {
+            (Some(v), Some(default)) if v.is_empty() => out.push_str(default),
+            (Some(v), _) => out.push_str(&v),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => return Err(format!("variable {} is not set", name)),
+        }
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolate_test() {
+        let lookup = |name: &str| match name {
+            "STAGE" => Some("prod".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            interpolate("/srv/${STAGE}/run.sh", lookup).unwrap(),
+            "/srv/prod/run.sh"
+        );
+        assert_eq!(
+            interpolate("${PORT:-8080} ${EMPTY:-x} ${EMPTY}.", lookup).unwrap(),
+            "8080 x ."
+        );
+        assert_eq!(
+            interpolate("$HOME $$ $${STAGE}", lookup).unwrap(),
+            "$HOME $$ ${STAGE}"
+        );
+        assert_eq!(
+            interpolate("${PORT}", lookup).unwrap_err(),
+            "variable PORT is not set"
+        );
+        assert!(interpolate("${STAGE", lookup).is_err());
+    }
+}
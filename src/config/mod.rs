@@ -1,2 +1,6 @@
+#[allow(clippy::module_inception)]
 pub mod config;
-mod error;
+pub mod env_file;
+pub mod error;
+mod include;
+mod interpolate;
@@ -0,0 +1,246 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{CString, OsString},
+    io,
+    net::TcpListener,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::FileTypeExt,
+        io::{AsRawFd, OwnedFd, RawFd},
+        net::UnixListener,
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use crate::config::config::{Socket, SocketBind};
+
+use super::sys::check;
+
+const LISTEN_FDS_START: RawFd = 3;
+const LISTEN_PID: &[u8] = b"LISTEN_PID=";
+// room for digits of any pid
+const PID_SIZE: usize = 20;
+
+/// listening sockets of program, opened once by sup and passed to every run
+/// of it from fd 3 on, with `LISTEN_FDS`, `LISTEN_FDNAMES` and `LISTEN_PID`
+/// as systemd socket activation does. connections arriving while program
+/// restarts wait in backlog of socket.
+#[derive(Default)]
+pub struct Sockets {
+    fds: Vec<OwnedFd>,
+    names: Vec<String>,
+    // unix sockets are removed on drop
+    paths: Vec<PathBuf>,
+}
+
+impl Sockets {
+    pub fn open(conf: &[Socket]) -> Result<Self> {
+        let mut sockets = Self::default();
+        for socket in conf {
+            let fd = match &socket.bind {
+                SocketBind::Tcp { address } => OwnedFd::from(
+                    TcpListener::bind(address).context(format!("bind {} failed", address))?,
+                ),
+                SocketBind::Unix { path } => {
+                    let path = Path::new(path);
+                    if path.metadata().is_ok_and(|m| m.file_type().is_socket()) {
+                        std::fs::remove_file(path)?;
+                    }
+                    let listener =
+                        UnixListener::bind(path).context(format!("bind {:?} failed", path))?;
+                    sockets.paths.push(path.to_path_buf());
+                    OwnedFd::from(listener)
+                }
+            };
+            sockets.fds.push(fd);
+            sockets.names.push(socket.name.clone());
+        }
+        Ok(sockets)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// exec of program run by cmd, with sockets passed to it. cmd tells no
+    /// cleared environment, so clean_env is passed along
+    pub fn exec(&self, cmd: &Command, clean_env: bool) -> Result<Exec> {
+        let path = CString::new(cmd.get_program().as_bytes())?;
+        let argv = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| CString::new(a.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut envs: BTreeMap<OsString, OsString> = match clean_env {
+            true => BTreeMap::new(),
+            false => env::vars_os().collect(),
+        };
+        for (k, v) in cmd.get_envs() {
+            match v {
+                Some(v) => envs.insert(k.to_os_string(), v.to_os_string()),
+                None => envs.remove(k),
+            };
+        }
+        envs.insert("LISTEN_FDS".into(), self.fds.len().to_string().into());
+        envs.insert("LISTEN_FDNAMES".into(), self.names.join(":").into());
+        envs.remove(&OsString::from("LISTEN_PID"));
+        let envp = envs
+            .into_iter()
+            .map(|(k, v)| {
+                let mut e = k.into_vec();
+                e.push(b'=');
+                e.extend(v.into_vec());
+                CString::new(e)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // digits are written by child, first nul ends the string
+        let mut pid = LISTEN_PID.to_vec();
+        pid.resize(pid.len() + PID_SIZE + 1, 0);
+
+        let argv_ptrs = pointers(argv.iter().map(|a| a.as_ptr()));
+        let envp_ptrs = pointers(
+            envp.iter()
+                .map(|e| e.as_ptr())
+                .chain([pid.as_ptr() as *const libc::c_char]),
+        );
+        let fds = self.fds.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+        Ok(Exec {
+            path,
+            _argv: argv,
+            _envp: envp,
+            pid,
+            argv_ptrs,
+            envp_ptrs,
+            moved: vec![-1; fds.len()],
+            min_fd: LISTEN_FDS_START + fds.len() as RawFd,
+            fds,
+        })
+    }
+}
+
+impl Drop for Sockets {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            if let Err(e) = std::fs::remove_file(path) {
+                error!("remove socket {:?} failed: {e}", path);
+            }
+        }
+    }
+}
+
+/// `LISTEN_PID` is only known after fork, so program with sockets is
+/// executed by Exec in pre_exec instead of by std. argv and envp are built
+/// before fork, so apply allocates nothing
+pub struct Exec {
+    path: CString,
+    // pointed to by argv_ptrs and envp_ptrs
+    _argv: Vec<CString>,
+    _envp: Vec<CString>,
+    pid: Vec<u8>,
+    argv_ptrs: Vec<*const libc::c_char>,
+    envp_ptrs: Vec<*const libc::c_char>,
+    fds: Vec<RawFd>,
+    // sockets are moved above min_fd first, so none is overwritten
+    moved: Vec<RawFd>,
+    min_fd: RawFd,
+}
+
+// pointers only refer to heap buffers owned by exec
+unsafe impl Send for Exec {}
+unsafe impl Sync for Exec {}
+
+impl Exec {
+    /// called last in forked child, returns only if exec failed
+    pub fn apply(&mut self) -> io::Result<()> {
+        for (fd, moved) in self.fds.iter().zip(self.moved.iter_mut()) {
+            *moved = check(unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, self.min_fd) })?;
+        }
+        for (i, moved) in self.moved.iter().enumerate() {
+            // dup2 clears close on exec of target
+            check(unsafe { libc::dup2(*moved, LISTEN_FDS_START + i as RawFd) })?;
+        }
+
+        let digits = &mut self.pid[LISTEN_PID.len()..LISTEN_PID.len() + PID_SIZE];
+        let mut pid = unsafe { libc::getpid() } as u64;
+        let mut n = 0;
+        loop {
+            digits[n] = b'0' + (pid % 10) as u8;
+            n += 1;
+            pid /= 10;
+            if pid == 0 {
+                break;
+            }
+        }
+        digits[..n].reverse();
+
+        unsafe {
+            libc::execve(
+                self.path.as_ptr(),
+                self.argv_ptrs.as_ptr(),
+                self.envp_ptrs.as_ptr(),
+            )
+        };
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// null terminated array of pointers
+fn pointers(p: impl Iterator<Item = *const libc::c_char>) -> Vec<*const libc::c_char> {
+    p.chain([std::ptr::null()]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn pass_sockets_test() {
+        let dir = env::temp_dir().join(format!("sup-activation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.sock");
+        let sockets = Sockets::open(&[
+            Socket {
+                name: "http".to_string(),
+                bind: SocketBind::Tcp {
+                    address: "127.0.0.1:0".to_string(),
+                },
+            },
+            Socket {
+                name: "admin".to_string(),
+                bind: SocketBind::Unix {
+                    path: path.to_str().unwrap().to_string(),
+                },
+            },
+        ])
+        .unwrap();
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args([
+            "-c",
+            "echo $LISTEN_FDS $LISTEN_FDNAMES; \
+             [ \"$LISTEN_PID\" = $$ ] && echo pid; \
+             [ -S /proc/self/fd/3 ] && [ -S /proc/self/fd/4 ] && echo sockets; \
+             echo $SUP_TEST",
+        ])
+        .env("SUP_TEST", "env");
+        let mut exec = sockets.exec(&cmd, false).unwrap();
+        unsafe {
+            cmd.pre_exec(move || exec.apply());
+        }
+        let out = cmd.output().unwrap();
+        let out = String::from_utf8_lossy(&out.stdout);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines, vec!["2 http:admin", "pid", "sockets", "env"]);
+
+        // unix socket is removed with sockets
+        drop(sockets);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
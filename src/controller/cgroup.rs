@@ -0,0 +1,181 @@
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+
+use crate::config::config::Cgroup as CgroupConf;
+
+use super::sys::write_file;
+
+const DAEMON_LEAF: &str = "sup.daemon";
+const CPU_PERIOD_MICROS: u64 = 100000;
+
+/// cgroup v2 of program, created under cgroup of sup as `sup-<name>`.
+/// controllers can only be enabled for children of a cgroup holding no
+/// process, so sup moves itself to a `sup.daemon` leaf first unless it runs
+/// in root cgroup. limits whose controller is not available are skipped.
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+    // cgroup.procs, opened by program before exec
+    procs: CString,
+}
+
+impl Cgroup {
+    /// set up once when sup starts, moving sup is logged as it changes
+    /// cgroup of sup itself
+    pub fn create(name: &str, conf: &CgroupConf) -> Result<Self> {
+        let (mount, own) = locate()?;
+        let mut base = mount.join(own.trim_start_matches('/'));
+        if base.ends_with(DAEMON_LEAF) {
+            // moved by cgroup of another program
+            base.pop();
+        }
+
+        let limits = [
+            (
+                "memory",
+                "memory.max",
+                conf.memory_max.map(|m| m.to_string()),
+            ),
+            (
+                "cpu",
+                "cpu.max",
+                conf.cpu_max_percent.map(|p| {
+                    let quota = (p / 100.0 * CPU_PERIOD_MICROS as f64) as u64;
+                    format!("{} {}", quota.max(1000), CPU_PERIOD_MICROS)
+                }),
+            ),
+            ("pids", "pids.max", conf.pids_max.map(|m| m.to_string())),
+        ];
+        let wanted: Vec<_> = limits.iter().filter(|(_, _, v)| v.is_some()).collect();
+        if !wanted.is_empty() && base != mount {
+            let leaf = base.join(DAEMON_LEAF);
+            fs::create_dir_all(&leaf).context(format!("create cgroup {:?} failed", leaf))?;
+            fs::write(leaf.join("cgroup.procs"), std::process::id().to_string())
+                .context(format!("move sup to cgroup {:?} failed", leaf))?;
+            info!(
+                "moved sup from cgroup {:?} to {:?} to enable controllers of programs",
+                base, leaf
+            );
+        }
+
+        let path = base.join(format!("sup-{}", name));
+        fs::create_dir_all(&path).context(format!("create cgroup {:?} failed", path))?;
+        for (controller, file, value) in wanted {
+            let enabled = fs::write(
+                base.join("cgroup.subtree_control"),
+                format!("+{}", controller),
+            );
+            if let Err(e) = enabled {
+                warn!(
+                    "controller {} of cgroup {:?} is not available, {} is not set: {e}",
+                    controller, base, file
+                );
+                continue;
+            }
+            let value = value.as_deref().unwrap_or("max");
+            fs::write(path.join(file), value).context(format!(
+                "set {} of cgroup {:?} to {} failed",
+                file, path, value
+            ))?;
+        }
+
+        Ok(Self {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_bytes())?,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// move program into cgroup before exec, so its first child is
+    /// already accounted
+    pub fn enter(&self) -> io::Result<()> {
+        write_file(&self.procs, b"0")
+    }
+
+    /// processes in cgroup
+    pub fn pids(&self) -> Result<Vec<u32>> {
+        let procs = fs::read_to_string(self.path.join("cgroup.procs"))
+            .context(format!("read processes of cgroup {:?} failed", self.path))?;
+        Ok(procs.lines().filter_map(|p| p.parse().ok()).collect())
+    }
+
+    /// remove cgroup once it holds no process
+    pub fn remove(&self) -> Result<()> {
+        fs::remove_dir(&self.path).context(format!("remove cgroup {:?} failed", self.path))
+    }
+
+    /// kill every process in cgroup, needs linux 5.14
+    pub fn kill(&self) -> Result<()> {
+        fs::write(self.path.join("cgroup.kill"), "1")
+            .context(format!("kill cgroup {:?} failed", self.path))
+    }
+}
+
+/// mount point of cgroup2 and cgroup of sup relative to it
+fn locate() -> Result<(PathBuf, String)> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let (root, mount) = mountinfo
+        .lines()
+        .find_map(|line| {
+            let (fields, fstype) = line.split_once(" - ")?;
+            if !fstype.starts_with("cgroup2 ") {
+                return None;
+            }
+            let fields: Vec<&str> = fields.split(' ').collect();
+            Some((fields.get(3)?.to_string(), PathBuf::from(fields.get(4)?)))
+        })
+        .ok_or_else(|| anyhow!("cgroup2 is not mounted"))?;
+
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| anyhow!("sup is not in a cgroup v2"))?;
+    let own = own.strip_prefix(root.as_str()).unwrap_or(own);
+    Ok((mount, own.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn cgroup_kill_test() {
+        let name = format!("test-{}", std::process::id());
+        let cgroup = match Cgroup::create(&name, &CgroupConf::default()) {
+            Ok(cgroup) => cgroup,
+            // cgroup2 is not writable
+            Err(_) => return,
+        };
+        let cg = cgroup.clone();
+        let mut cmd = std::process::Command::new("/bin/sh");
+        cmd.args(["-c", "sleep 30 & sleep 30"]);
+        unsafe {
+            cmd.pre_exec(move || cg.enter());
+        }
+        let mut child = cmd.spawn().unwrap();
+        let procs = || fs::read_to_string(cgroup.path().join("cgroup.procs")).unwrap();
+        while procs().lines().count() < 2 {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(procs().lines().any(|p| p == child.id().to_string()));
+
+        cgroup.kill().unwrap();
+        child.wait().unwrap();
+        while !procs().is_empty() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        cgroup.remove().unwrap();
+    }
+}
@@ -1,43 +1,183 @@
-use anyhow::{Context, Ok, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{anyhow, Context, Ok, Result};
 use log::debug;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::UnixStream,
+use tokio::net::{
+    unix::{OwnedReadHalf, OwnedWriteHalf},
+    UnixStream,
 };
 
-use super::command::{Request, Response};
+use super::{
+    command::{AttachInput, Command, Request, Response, ResponseBody},
+    event::Event,
+    protocol::{read_frame, write_frame, Hello, HelloAck},
+};
 
 pub struct Client {
     s: String,
+    next_id: AtomicU64,
 }
 
 impl Client {
     pub fn new(socket_path: String) -> Self {
-        Self { s: socket_path }
+        Self {
+            s: socket_path,
+            next_id: AtomicU64::new(1),
+        }
     }
 
+    /// send request and return its first response, use request_stream for
+    /// commands answered by multiple frames such as tail
     pub async fn request(&self, req: Request) -> Result<Response> {
+        self.request_stream(req)
+            .await?
+            .next()
+            .await?
+            .ok_or_else(|| anyhow!("connection closed before response"))
+    }
+
+    pub async fn request_stream(&self, req: Request) -> Result<ResponseStream> {
+        let (mut stream, wr) = self.send(req).await?;
+        // server treats a closed write side as a gone client
+        stream.wr = Some(wr);
+        Ok(stream)
+    }
+
+    /// attach to program, output of program arrives on the response stream
+    /// and input is sent by the returned writer
+    pub async fn attach(&self) -> Result<(ResponseStream, AttachWriter)> {
+        let (stream, wr) = self.send(Request::new(Command::Attach)).await?;
+        Ok((stream, AttachWriter { wr }))
+    }
+
+    /// subscribe to events of program, stream lasts until dropped
+    pub async fn events(&self) -> Result<EventStream> {
+        Ok(EventStream {
+            stream: self.request_stream(Request::new(Command::Events)).await?,
+        })
+    }
+
+    async fn send(&self, mut req: Request) -> Result<(ResponseStream, OwnedWriteHalf)> {
+        req.id = self.next_id.fetch_add(1, Ordering::Relaxed);
         debug!("sending request {:?}", req);
-        let req: Vec<u8> = req.into();
+        let mut stream = self.connect().await?;
+
+        write_frame(&mut stream, &req)
+            .await
+            .context(format!("write request to {} failed", self.s))?;
+        debug!("write request done");
+
+        let (rd, wr) = stream.into_split();
+        Ok((
+            ResponseStream {
+                rd,
+                wr: None,
+                id: req.id,
+                done: false,
+            },
+            wr,
+        ))
+    }
+
+    /// connect to server and finish handshake
+    async fn connect(&self) -> Result<UnixStream> {
         let mut stream = UnixStream::connect(&self.s)
             .await
             .context(format!("connect to {} failed", self.s))?;
 
-        stream
-            .write(&req)
+        write_frame(&mut stream, &Hello::new())
             .await
-            .context(format!("write request to {} failed", self.s))?;
+            .context("write hello failed")?;
+        let ack = read_frame(&mut stream)
+            .await
+            .context("read hello ack failed")?
+            .ok_or_else(|| anyhow!("connection closed during handshake"))?;
+        match serde_json::from_slice(&ack).context("decode hello ack failed")? {
+            HelloAck::Accepted { version, sup_pid } => {
+                debug!("handshake done, server version {version}, sup pid {sup_pid}");
+                Ok(stream)
+            }
+            HelloAck::Rejected { message, .. } => Err(anyhow!("handshake rejected: {message}")),
+        }
+    }
+}
 
-        stream.shutdown().await?;
-        debug!("write request done");
+/// responses of one request, ends after the terminal response
+pub struct ResponseStream {
+    rd: OwnedReadHalf,
+    wr: Option<OwnedWriteHalf>,
+    id: u64,
+    done: bool,
+}
 
-        let mut resp = Vec::<u8>::new();
-        let mut buf = [0; 1024];
-        while stream.read(&mut buf).await.context("read resp failed")? != 0 {
-            debug!("read resp");
-            resp.append(&mut buf.to_vec());
+impl ResponseStream {
+    pub async fn next(&mut self) -> Result<Option<Response>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let resp = match read_frame(&mut self.rd).await.context("read resp failed")? {
+            Some(resp) => resp,
+            None => return Err(anyhow!("connection closed before terminal response")),
+        };
+        let resp: Response = serde_json::from_slice(&resp).context("decode resp failed")?;
+        debug!("read resp done");
+        // server answers a request it can not decode with id 0 if it can
+        // not find the id either
+        if let (0, ResponseBody::Error { message, .. }) = (resp.id(), resp.body()) {
+            return Err(anyhow!("protocol error: {message}"));
+        }
+        if resp.id() != self.id {
+            return Err(anyhow!(
+                "response id {} mismatches request id {}",
+                resp.id(),
+                self.id
+            ));
         }
 
-        Ok(resp.into())
+        self.done = resp.is_terminal();
+        Ok(Some(resp))
+    }
+}
+
+/// events of program in the order they happen
+pub struct EventStream {
+    stream: ResponseStream,
+}
+
+impl EventStream {
+    /// next event, none if server ended the stream
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        while let Some(resp) = self.stream.next().await? {
+            match resp.body() {
+                ResponseBody::Event { event } => return Ok(Some(event.clone())),
+                ResponseBody::Error { message, .. } => return Err(anyhow!("{message}")),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// sends input of an attached terminal to program stdin
+pub struct AttachWriter {
+    wr: OwnedWriteHalf,
+}
+
+impl AttachWriter {
+    pub async fn input(&mut self, data: &[u8]) -> Result<()> {
+        let input = AttachInput::Input {
+            data: data.to_vec(),
+        };
+        write_frame(&mut self.wr, &input)
+            .await
+            .context("write attach input failed")
+    }
+
+    /// server answers detach with the terminal response
+    pub async fn detach(&mut self) -> Result<()> {
+        write_frame(&mut self.wr, &AttachInput::Detach)
+            .await
+            .context("write detach failed")
     }
 }
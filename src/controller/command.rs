@@ -1,11 +1,16 @@
-/// vec<u8> is standard data structure for creating request && response
-use std::{fmt::Display, ops::Index};
+use std::fmt::Display;
 
 use clap::Subcommand;
+use serde_derive::{Deserialize, Serialize};
 
-const BYTES_PER_PID: usize = 4;
+use super::{
+    controller::{ExitReason, ProcessState, ResourceUsage, RestartReason},
+    error::ErrorKind,
+    event::Event,
+};
 
-#[derive(Debug, Subcommand)]
+#[derive(Debug, Clone, Copy, PartialEq, Subcommand, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Command {
     #[command(about = "start program asynchronously")]
     Start,
@@ -19,137 +24,333 @@ pub enum Command {
     Reload,
     #[command(about = "print status of program")]
     Status,
+    #[command(about = "rotate log of program asynchronously")]
+    Rotate,
+    #[command(about = "print last lines of program log")]
+    Tail {
+        // keep printing lines appended to log
+        #[arg(short, long)]
+        follow: bool,
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        // print stderr log instead of stdout log
+        #[arg(long)]
+        stderr: bool,
+    },
+    #[command(about = "attach terminal to program stdin and output, detach with ctrl-]")]
+    Attach,
+    #[command(about = "print events of program as they happen")]
+    Events,
     #[command(about = "exit the sup daemon and the process asynchronously")]
     Exit,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Request {
-    pub cmd: Option<Command>,
+    // id is assigned by client and echoed in response
+    pub id: u64,
+    pub cmd: Command,
 }
 
 impl Request {
     pub fn new(cmd: Command) -> Self {
-        Self { cmd: Some(cmd) }
+        Self { id: 0, cmd }
     }
 }
 
-impl From<Vec<u8>> for Request {
-    fn from(code: Vec<u8>) -> Self {
-        if code.len() != 1 {
-            return Self { cmd: None };
-        }
-        Self {
-            cmd: match code.get(0).unwrap() {
-                0 => Some(Command::Start),
-                1 => Some(Command::Stop),
-                2 => Some(Command::Restart),
-                3 => Some(Command::Kill),
-                4 => Some(Command::Reload),
-                5 => Some(Command::Status),
-                6 => Some(Command::Exit),
-                _ => None,
-            },
-        }
-    }
+/// frames sent by client after an attach request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AttachInput {
+    Input { data: Vec<u8> },
+    Detach,
 }
 
-impl From<Request> for Vec<u8> {
-    fn from(c: Request) -> Self {
-        if let Some(cmd) = c.cmd {
-            match cmd {
-                Command::Start => vec![0],
-                Command::Stop => vec![1],
-                Command::Restart => vec![2],
-                Command::Kill => vec![3],
-                Command::Reload => vec![4],
-                Command::Status => vec![5],
-                Command::Exit => vec![6],
-            }
-        } else {
-            vec![7]
-        }
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProgramStatus {
+    pub name: String,
+    pub state: ProcessState,
+    pub pid: Option<u32>,
+    // seconds since program is spawned
+    pub uptime: Option<u64>,
+    pub restarts: u64,
+    pub last_exit: Option<ExitReason>,
+    pub log_path: String,
+    // none until first sample of running program
+    #[serde(default)]
+    pub usage: Option<ResourceUsage>,
+    // why sup restarted program at last exit
+    #[serde(default)]
+    pub last_exit_reason: Option<RestartReason>,
+}
+
+/// body of response, tagged by "result" in json
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ResponseBody {
+    Success {
+        command: Command,
+        message: String,
+        // pid of program after command executed
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pid: Option<u32>,
+    },
+    Status {
+        programs: Vec<ProgramStatus>,
+    },
+    // part of a streaming response, more frames follow
+    Output {
+        data: String,
+    },
+    // part of events stream, more frames follow
+    Event {
+        event: Event,
+    },
+    Error {
+        kind: ErrorKind,
+        message: String,
+    },
 }
-#[derive(Debug)]
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Response {
-    message: String,
+    id: u64,
     sup_pid: Option<u32>,
+    #[serde(flatten)]
+    body: ResponseBody,
 }
 
 impl Response {
-    const INVALID_PID: u32 = 0;
+    pub fn new(body: ResponseBody, sup_pid: Option<u32>) -> Self {
+        Self {
+            id: 0,
+            body,
+            sup_pid,
+        }
+    }
 
-    pub fn new(message: String, sup_pid: Option<u32>) -> Self {
-        Self { message, sup_pid }
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
     }
 
-    fn marshal_msg(self) -> Vec<u8> {
-        self.message.into_bytes()
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
-    fn marshal_sup_pid(&self) -> Vec<u8> {
-        let pid = match self.sup_pid {
-            Some(pid) => pid,
-            None => Self::INVALID_PID,
-        };
+    pub fn body(&self) -> &ResponseBody {
+        &self.body
+    }
+
+    pub fn sup_pid(&self) -> Option<u32> {
+        self.sup_pid
+    }
 
-        vec![
-            (pid >> 24) as u8,
-            (pid >> 16) as u8,
-            (pid >> 8) as u8,
-            pid as u8,
-        ]
+    pub fn is_error(&self) -> bool {
+        matches!(self.body, ResponseBody::Error { .. })
     }
 
-    fn unmarshal_msg(&mut self, v: Vec<u8>) {
-        self.message = String::from_utf8(v).unwrap();
+    /// terminal response is the last frame of a request
+    pub fn is_terminal(&self) -> bool {
+        !matches!(
+            self.body,
+            ResponseBody::Output { .. } | ResponseBody::Event { .. }
+        )
     }
 
-    fn unmarshal_sup_pid(&mut self, v: Vec<u8>) {
-        let mut pid = match self.sup_pid {
-            Some(pid) => pid,
-            None => return,
+    /// status of programs as an aligned table
+    pub fn status_table(&self) -> String {
+        let mut rows = vec![[
+            "NAME".to_string(),
+            "STATE".to_string(),
+            "PID".to_string(),
+            "SUP_PID".to_string(),
+            "UPTIME".to_string(),
+            "CPU".to_string(),
+            "RSS".to_string(),
+            "RESTARTS".to_string(),
+            "LAST_EXIT".to_string(),
+            "LOG".to_string(),
+        ]];
+        let programs = match &self.body {
+            ResponseBody::Status { programs } => programs.as_slice(),
+            _ => &[],
         };
-        for (i, e) in v.into_iter().enumerate() {
-            pid += (e as u32) << (8 * i);
+        for st in programs {
+            rows.push([
+                st.name.clone(),
+                st.state.to_string(),
+                format_option(st.pid),
+                format_option(self.sup_pid),
+                st.uptime
+                    .map(format_uptime)
+                    .unwrap_or_else(|| "-".to_string()),
+                st.usage
+                    .map(|u| format!("{:.1}%", u.cpu_percent))
+                    .unwrap_or_else(|| "-".to_string()),
+                st.usage
+                    .map(|u| format_bytes(u.rss_bytes))
+                    .unwrap_or_else(|| "-".to_string()),
+                st.restarts.to_string(),
+                format_exit(st.last_exit, st.last_exit_reason),
+                st.log_path.clone(),
+            ]);
         }
 
-        if pid == Self::INVALID_PID {
-            self.sup_pid = None;
-            return;
+        let mut widths = [0; 10];
+        for row in &rows {
+            for (i, col) in row.iter().enumerate() {
+                widths[i] = widths[i].max(col.len());
+            }
         }
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, col)| format!("{:width$}", col, width = widths[i]))
+                    .collect::<Vec<_>>()
+                    .join("  ")
+                    .trim_end()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
 
-        self.sup_pid = Some(pid);
+fn format_option<T: Display>(v: Option<T>) -> String {
+    match v {
+        Some(v) => v.to_string(),
+        None => "-".to_string(),
     }
 }
 
-impl From<Response> for Vec<u8> {
-    fn from(r: Response) -> Self {
-        let mut res = Vec::<u8>::new();
-        res.append(&mut r.marshal_sup_pid());
-        res.append(&mut r.marshal_msg());
-        res
+fn format_exit(exit: Option<ExitReason>, reason: Option<RestartReason>) -> String {
+    let exit = match exit {
+        Some(ExitReason::Code(c)) => format!("code {}", c),
+        Some(ExitReason::Signal(s)) => format!("signal {}", s),
+        None => return "-".to_string(),
+    };
+    match reason {
+        Some(reason) => format!("{} ({})", exit, reason),
+        None => exit,
     }
 }
 
-impl From<Vec<u8>> for Response {
-    fn from(v: Vec<u8>) -> Self {
-        let mut s = Self {
-            message: String::new(),
-            sup_pid: Some(0),
-        };
-        s.unmarshal_sup_pid(v.index(..BYTES_PER_PID).to_vec());
-        s.unmarshal_msg(v.index(BYTES_PER_PID..).to_vec());
-        s
+fn format_bytes(bytes: u64) -> String {
+    let mut v = bytes as f64;
+    for unit in ["B", "K", "M", "G"] {
+        if v < 1024.0 {
+            return format!("{:.1}{}", v, unit);
+        }
+        v /= 1024.0;
+    }
+    format!("{:.1}T", v)
+}
+
+fn format_uptime(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let hms = format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    );
+    match days {
+        0 => hms,
+        d => format!("{}d {}", d, hms),
     }
 }
 
 impl Display for Response {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.sup_pid {
-            Some(pid) => write!(f, "{}, pid is {}", self.message, pid),
-            None => write!(f, "{}", self.message),
+        match &self.body {
+            ResponseBody::Success {
+                message,
+                pid: Some(pid),
+                ..
+            } => write!(f, "{}, pid is {}", message, pid),
+            ResponseBody::Success { message, .. } => write!(f, "{}", message),
+            ResponseBody::Status { .. } => write!(f, "{}", self.status_table()),
+            ResponseBody::Output { data } => write!(f, "{}", data),
+            ResponseBody::Event { event } => write!(f, "{}", event),
+            ResponseBody::Error { message, .. } => write!(f, "{}", message),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::controller::{Limit, LimitBreach};
+
+    #[test]
+    fn marshal_response_test() {
+        let resp = Response::new(
+            ResponseBody::Status {
+                programs: vec![ProgramStatus {
+                    name: "run".to_string(),
+                    state: ProcessState::Running,
+                    pid: Some(42),
+                    uptime: Some(90061),
+                    restarts: 2,
+                    last_exit: Some(ExitReason::Signal(15)),
+                    log_path: "/tmp/run.log".to_string(),
+                    usage: Some(ResourceUsage {
+                        cpu_percent: 12.5,
+                        cpu_seconds: 3.0,
+                        rss_bytes: 10 << 20,
+                        fds: 4,
+                        threads: 1,
+                        read_bytes: 0,
+                        write_bytes: 0,
+                    }),
+                    last_exit_reason: Some(RestartReason::Limit(LimitBreach {
+                        limit: Limit::Rss,
+                        value: (600 << 20) as f64,
+                        max: (512 << 20) as f64,
+                    })),
+                }],
+            },
+            Some(4242),
+        );
+        let v = serde_json::to_vec(&resp).unwrap();
+        let resp: Response = serde_json::from_slice(&v).unwrap();
+
+        assert_eq!(resp.sup_pid(), Some(4242));
+        assert_eq!(
+            resp.to_string(),
+            "NAME  STATE    PID  SUP_PID  UPTIME       CPU    RSS    RESTARTS  LAST_EXIT              LOG\n\
+             run   RUNNING  42   4242     1d 01:01:01  12.5%  10.0M  2         signal 15 (rss limit)  /tmp/run.log"
+        );
+    }
+
+    #[test]
+    fn response_json_test() {
+        let resp = Response::new(
+            ResponseBody::Success {
+                command: Command::Start,
+                message: "start success".to_string(),
+                pid: Some(42),
+            },
+            Some(4242),
+        );
+        assert_eq!(resp.to_string(), "start success, pid is 42");
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"id":0,"sup_pid":4242,"result":"success","command":"start","message":"start success","pid":42}"#
+        );
+
+        let resp = Response::new(
+            ResponseBody::Error {
+                kind: ErrorKind::Busy,
+                message: "start failed: another command is executing".to_string(),
+            },
+            Some(4242),
+        );
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"id":0,"sup_pid":4242,"result":"error","kind":"busy","message":"start failed: another command is executing"}"#
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+use anyhow::Result;
+use tokio::process::Command;
+
+use crate::config::config::Process;
+
+use super::{
+    activation::Exec,
+    cgroup::Cgroup,
+    credential::Credential,
+    rlimit::Rlimits,
+    sandbox::{Joined, Sandbox},
+};
+
+/// cgroup, limits, sandbox and credential of program, exec probes run with
+/// its limits and credential too so they can't do more than program itself
+pub struct Confinement {
+    work_dir: String,
+    cgroup: Option<Cgroup>,
+    rlimits: Rlimits,
+    umask: Option<u32>,
+    sandbox: Option<Sandbox>,
+    // namespaces of running program, entered by its probes instead of sandbox
+    joined: Option<Joined>,
+    cred: Credential,
+}
+
+impl Confinement {
+    pub fn new(conf: &Process, cgroup: Option<Cgroup>) -> Result<Self> {
+        let sandbox = match &conf.sandbox {
+            Some(sandbox) => Some(Sandbox::new(sandbox, &conf.work_dir)?),
+            None => None,
+        };
+        Ok(Self {
+            work_dir: conf.work_dir.clone(),
+            cgroup,
+            rlimits: Rlimits::new(&conf.rlimits)?,
+            umask: conf.umask,
+            sandbox,
+            joined: None,
+            cred: Credential::lookup(conf.user.as_deref(), conf.group.as_deref())?,
+        })
+    }
+
+    /// confinement of probes of program running as pid. they see network
+    /// and mounts of program but stay out of its cgroup, so they neither
+    /// count against its limits nor are killed along with it
+    pub fn probe(conf: &Process, pid: u32) -> Result<Self> {
+        let mut confinement = Self::new(conf, None)?;
+        if let Some(sandbox) = confinement.sandbox.take() {
+            confinement.joined = Some(sandbox.join(pid)?);
+        }
+        Ok(confinement)
+    }
+
+    pub fn credential(&self) -> &Credential {
+        &self.cred
+    }
+
+    /// run cmd in its own process group so kill reaches all children,
+    /// limits are raised and sandbox is set up before privileges are
+    /// dropped, program with sockets is executed last by exec
+    pub fn confine(self, cmd: &mut Command, mut exec: Option<Exec>) {
+        // work dir is entered by sandbox after chroot and by probes after
+        // joining mounts of program
+        if !self.sandbox.as_ref().is_some_and(Sandbox::chroot) && self.joined.is_none() {
+            cmd.current_dir(&self.work_dir);
+        }
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                if let Some(cgroup) = &self.cgroup {
+                    cgroup.enter()?;
+                }
+                self.rlimits.apply()?;
+                if let Some(umask) = self.umask {
+                    libc::umask(umask as libc::mode_t);
+                }
+                if let Some(sandbox) = &self.sandbox {
+                    sandbox.apply()?;
+                }
+                if let Some(joined) = &self.joined {
+                    joined.apply()?;
+                }
+                self.cred.apply()?;
+                match &mut exec {
+                    Some(exec) => exec.apply(),
+                    None => Ok(()),
+                }
+            });
+        }
+    }
+}
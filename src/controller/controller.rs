@@ -1,73 +1,1545 @@
 use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicUsize, Ordering},
+    fmt::Display,
+    io,
+    os::unix::process::ExitStatusExt,
+    process::{ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::{Ok, Result};
-use tokio::process::Command;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    process::{Child, ChildStdin, Command},
+    sync::{broadcast, watch, Mutex as AsyncMutex},
+    time,
+};
+
+use crate::{
+    config::{
+        config::{
+            Health, LimitAction, Log, Process, ProcessRestartStrategy, Program, Readiness,
+            ReadyCheck, RestartMode, StopSignal,
+        },
+        env_file,
+    },
+    rotater::rotater::{LogStats, RotateHandle},
+};
+
+use super::{
+    activation::Sockets,
+    cgroup::Cgroup,
+    command::{Command as mCommand, ProgramStatus},
+    confinement::Confinement,
+    credential::Credential,
+    error::Error,
+    event::{Event, EventKind},
+    notify::{Notification, NotifySocket},
+    output::LogWriter,
+    probe, procfs,
+    readiness::ReadyWait,
+};
+
+const RESTART_INTERVAL: Duration = Duration::from_secs(1);
+const ATTACH_CHANNEL_LENGTH: usize = 256;
+const EVENT_CHANNEL_LENGTH: usize = 256;
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+const RETIRE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CGROUP_REMOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// STARTING: program is spawned and has not been up for startSeconds, or
+/// is not ready yet if readiness is set
+/// BACKOFF: program exited while starting, it is retried startRetries times
+/// UNHEALTHY: running program failed failureThreshold health probes in a row
+/// EXITED: program exited after running, restart strategy decides what's next
+/// FATAL: program could not be started after retries
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ProcessState {
+    Stopped,
+    Starting,
+    Running,
+    Unhealthy,
+    Backoff,
+    Stopping,
+    Exited,
+    Fatal,
+}
+
+impl Display for ProcessState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Stopped => "STOPPED",
+            Self::Starting => "STARTING",
+            Self::Running => "RUNNING",
+            Self::Unhealthy => "UNHEALTHY",
+            Self::Backoff => "BACKOFF",
+            Self::Stopping => "STOPPING",
+            Self::Exited => "EXITED",
+            Self::Fatal => "FATAL",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitReason {
+    Code(i32),
+    Signal(i32),
+}
+
+impl From<ExitStatus> for ExitReason {
+    fn from(s: ExitStatus) -> Self {
+        match (s.code(), s.signal()) {
+            (Some(code), _) => Self::Code(code),
+            (None, Some(sig)) => Self::Signal(sig),
+            (None, None) => Self::Code(-1),
+        }
+    }
+}
+
+/// resource usage of program and its descendants sampled from /proc
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    // cpu time in last sample interval, 100 for a full core
+    pub cpu_percent: f64,
+    pub cpu_seconds: f64,
+    pub rss_bytes: u64,
+    pub fds: u64,
+    pub threads: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
 
-use crate::config::config::{Process, ProcessRestartStrategy};
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    Rss,
+    CpuPercent,
+}
 
-use super::command::Command as mCommand;
+/// usage of program stayed above limit for limitGraceSeconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LimitBreach {
+    pub limit: Limit,
+    pub value: f64,
+    pub max: f64,
+}
+
+impl Display for LimitBreach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.limit {
+            Limit::Rss => write!(f, "rss {} bytes over {}", self.value, self.max),
+            Limit::CpuPercent => write!(f, "cpu {:.1}% over {:.1}%", self.value, self.max),
+        }
+    }
+}
+
+/// why sup restarted program, kept with the exit it caused
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RestartReason {
+    Limit(LimitBreach),
+    Unhealthy,
+    Watchdog,
+}
+
+impl Display for RestartReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Limit(LimitBreach {
+                limit: Limit::Rss, ..
+            }) => write!(f, "rss limit"),
+            Self::Limit(LimitBreach {
+                limit: Limit::CpuPercent,
+                ..
+            }) => write!(f, "cpu limit"),
+            Self::Unhealthy => write!(f, "unhealthy"),
+            Self::Watchdog => write!(f, "watchdog timeout"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RunInfo {
+    pid: Option<u32>,
+    started_at: Option<Instant>,
+    restarts: u64,
+    last_exit: Option<ExitReason>,
+    usage: Option<ResourceUsage>,
+    // set while sup restarts program, moved to last_exit_reason once it
+    // exits
+    pending_reason: Option<RestartReason>,
+    last_exit_reason: Option<RestartReason>,
+}
 
 pub struct ProcessController {
     exec_status: AtomicUsize, // 0 ==> not running 1 ==> running
-    cmd: Command,
-    args: Option<Vec<String>>,
-    dir: String,
-    env: Option<HashMap<String, String>>,
-    interval: u64,
-    restart: ProcessRestartStrategy,
+    name: String,
+    conf: Process,
+    log: Log,
+    health: Option<Health>,
+    readiness: Option<Readiness>,
+    rotate: RotateHandle,
+    info: Mutex<RunInfo>,
+    state: watch::Sender<ProcessState>,
+    // set by stop && kill, exit of program is expected and never restarted
+    stopping: AtomicBool,
+    // stdin pipe of running program if process.stdin is set
+    stdin: AsyncMutex<Option<ChildStdin>>,
+    // none if not configured or cgroup v2 is not writable
+    cgroup: Option<Cgroup>,
+    // passed to program by NOTIFY_SOCKET if readiness is notify or
+    // watchdogSeconds is set
+    notify: Option<NotifySocket>,
+    // opened by sup and kept across restarts
+    sockets: Sockets,
+    attached: broadcast::Sender<String>,
+    events: broadcast::Sender<Event>,
 }
 
 impl ProcessController {
-    pub async fn new(conf: Process) -> Result<Self> {
-        let pc = Self {
-            exec_status: AtomicUsize::new(0),
-            cmd: Command::new(conf.path),
-            args: conf.args,
-            dir: conf.work_dir,
-            env: conf.envs,
-            interval: conf.start_interval,
-            restart: conf.restart_strategy,
+    pub fn new(
+        conf: Program,
+        sockets: Sockets,
+        cgroup: Option<Cgroup>,
+        rotate: RotateHandle,
+    ) -> Arc<Self> {
+        let (state, _) = watch::channel(ProcessState::Stopped);
+        let notified = matches!(
+            conf.readiness,
+            Some(Readiness {
+                check: ReadyCheck::Notify {},
+                ..
+            })
+        );
+        let notify = match notified || conf.process.watchdog.is_some() {
+            // program of unknown user fails to spawn anyway
+            true => match NotifySocket::bind(&conf.name, owner_of(&conf.process)) {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    error!("program {} runs without notify socket: {e:#}", conf.name);
+                    None
+                }
+            },
+            false => None,
         };
-        if conf.auto_start {
-            Self::start_cmd(&pc).await?
+        let pc = Arc::new(Self {
+            exec_status: AtomicUsize::new(0),
+            name: conf.name,
+            conf: conf.process,
+            log: conf.log,
+            health: conf.health,
+            readiness: conf.readiness,
+            rotate,
+            info: Mutex::new(RunInfo::default()),
+            state,
+            stopping: AtomicBool::new(false),
+            stdin: AsyncMutex::new(None),
+            cgroup,
+            notify,
+            sockets,
+            attached: broadcast::channel(ATTACH_CHANNEL_LENGTH).0,
+            events: broadcast::channel(EVENT_CHANNEL_LENGTH).0,
+        });
+        tokio::spawn(Self::forward_rotated(
+            Arc::downgrade(&pc),
+            pc.rotate.subscribe_rotated(),
+        ));
+        tokio::spawn(Self::sample_usage(Arc::downgrade(&pc)));
+        if pc.health.is_some() {
+            tokio::spawn(Self::check_health(Arc::downgrade(&pc)));
         }
-        Ok(pc)
+        if let (Some(watchdog), Some(notify)) = (pc.conf.watchdog, &pc.notify) {
+            tokio::spawn(Self::check_watchdog(
+                Arc::downgrade(&pc),
+                notify.subscribe(),
+                Duration::from_secs(watchdog),
+            ));
+        }
+        pc
     }
 
-    pub async fn exec_cmd(&self, cmd: mCommand) -> Result<()> {
+    /// start program if autoStart is set, called once event subscribers
+    /// of daemon are ready. readiness is not waited for, so programs are
+    /// started at once
+    pub async fn auto_start(self: &Arc<Self>) -> Result<()> {
+        if self.conf.auto_start {
+            self.start_cmd(false).await?;
+        }
+        Ok(())
+    }
+
+    /// execute command and return pid of program after execution
+    pub async fn exec_cmd(self: &Arc<Self>, cmd: mCommand) -> Result<Option<u32>> {
         if self.is_executing() {
-            return Ok(());
+            return Err(Error::Busy.into());
         }
 
-        self.start_cmd().await;
-        self.set_executing();
+        let res = match cmd {
+            mCommand::Start => self.start_cmd(true).await.map(Some),
+            mCommand::Stop => self.stop_cmd().await.map(|_| None),
+            mCommand::Exit => self.exit_cmd().await.map(|_| None),
+            mCommand::Restart => match self.conf.restart_mode {
+                RestartMode::StartFirst if self.pid().is_some() => {
+                    self.replace_cmd().await.map(Some)
+                }
+                _ => match self.stop_cmd().await {
+                    Ok(_) => {
+                        self.info.lock().unwrap().restarts += 1;
+                        self.start_cmd(true).await.map(Some)
+                    }
+                    Err(e) => Err(e),
+                },
+            },
+            mCommand::Kill => self.kill_cmd().await.map(|_| None),
+            mCommand::Reload => self.reload_cmd().map(Some),
+            mCommand::Rotate => {
+                self.rotate.add_rotate_task(self.log.clone()).await;
+                if let Some(log) = self.stderr_log() {
+                    self.rotate.add_rotate_task(log).await;
+                }
+                Ok(self.pid())
+            }
+            mCommand::Status | mCommand::Tail { .. } | mCommand::Attach | mCommand::Events => {
+                Ok(self.pid())
+            }
+        };
+        self.set_idle();
+        res
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.info.lock().unwrap().pid
+    }
+
+    pub fn state(&self) -> ProcessState {
+        *self.state.borrow()
+    }
+
+    /// path of stdout log, or stderr log if it is written separately
+    pub fn log_path(&self, stderr: bool) -> Result<String> {
+        if !stderr {
+            return Ok(self.log.path.clone());
+        }
+        match &self.log.stderr_path {
+            Some(path) => Ok(path.clone()),
+            None => Err(Error::InvalidArgument(
+                "stderr is written to stdout log, set stderrPath to log it separately".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    /// output of program from now on, for attached terminals. it is decoded
+    /// per stream before being sent, so characters cut between reads stay
+    /// whole
+    pub fn subscribe_output(&self) -> broadcast::Receiver<String> {
+        self.attached.subscribe()
+    }
+
+    /// events of program from now on
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, kind: EventKind) {
+        // no subscriber is not an error
+        let _ = self.events.send(Event::new(&self.name, kind));
+    }
+
+    /// sample usage of running program every SAMPLE_INTERVAL, cpu percent
+    /// is measured between samples or since spawn for the first one
+    async fn sample_usage(pc: Weak<Self>) {
+        let mut last: Option<(u32, Instant, f64)> = None;
+        // since when usage is above maxRss and maxCpuPercent
+        let mut over_since = [None; 2];
+        loop {
+            time::sleep(SAMPLE_INTERVAL).await;
+            let pc = match pc.upgrade() {
+                Some(pc) => pc,
+                None => return,
+            };
+            let (pid, started_at) = {
+                let info = pc.info.lock().unwrap();
+                match (info.pid, info.started_at) {
+                    (Some(pid), Some(started_at)) => (pid, started_at),
+                    _ => continue,
+                }
+            };
+
+            let sample = match tokio::task::spawn_blocking(move || procfs::sample_tree(pid)).await {
+                Ok(Ok(sample)) => sample,
+                Ok(Err(e)) => {
+                    debug!("sample program {} failed: {e}", pid);
+                    continue;
+                }
+                Err(e) => {
+                    error!("sample program {} failed: {e}", pid);
+                    continue;
+                }
+            };
+            let now = Instant::now();
+            let (since, cpu_before) = match last {
+                Some((p, at, cpu)) if p == pid => (at, cpu),
+                _ => {
+                    over_since = [None; 2];
+                    (started_at, 0.0)
+                }
+            };
+            last = Some((pid, now, sample.cpu_seconds));
+            let elapsed = now.duration_since(since).as_secs_f64();
+            let cpu_percent = match elapsed {
+                e if e > 0.0 => (sample.cpu_seconds - cpu_before).max(0.0) / e * 100.0,
+                _ => 0.0,
+            };
+
+            let usage = ResourceUsage {
+                cpu_percent,
+                cpu_seconds: sample.cpu_seconds,
+                rss_bytes: sample.rss_bytes,
+                fds: sample.fds,
+                threads: sample.threads,
+                read_bytes: sample.read_bytes,
+                write_bytes: sample.write_bytes,
+            };
+            {
+                let mut info = pc.info.lock().unwrap();
+                if info.pid != Some(pid) {
+                    continue;
+                }
+                info.usage = Some(usage);
+            }
+            if let Some(breach) = pc.check_limits(&usage, &mut over_since, now) {
+                over_since = [None; 2];
+                pc.handle_breach(breach).await;
+            }
+        }
+    }
+
+    /// breach of first limit usage stayed above for limitGraceSeconds
+    fn check_limits(
+        &self,
+        usage: &ResourceUsage,
+        over_since: &mut [Option<Instant>; 2],
+        now: Instant,
+    ) -> Option<LimitBreach> {
+        let grace = Duration::from_secs(self.conf.limit_grace);
+        let limits = [
+            (
+                Limit::Rss,
+                usage.rss_bytes as f64,
+                self.conf.max_rss.map(|m| m as f64),
+            ),
+            (
+                Limit::CpuPercent,
+                usage.cpu_percent,
+                self.conf.max_cpu_percent,
+            ),
+        ];
+        for ((limit, value, max), since) in limits.into_iter().zip(over_since.iter_mut()) {
+            match max {
+                Some(max) if value > max => {
+                    let since = *since.get_or_insert(now);
+                    if now.duration_since(since) >= grace {
+                        return Some(LimitBreach { limit, value, max });
+                    }
+                }
+                _ => *since = None,
+            }
+        }
+        None
+    }
+
+    /// emit breach and restart program if limitAction is restart, the
+    /// breach is kept as reason of the exit
+    async fn handle_breach(self: &Arc<Self>, breach: LimitBreach) {
+        let restart = self.conf.limit_action == LimitAction::Restart;
+        warn!("program {} exceeded limit: {}", self.conf.path, breach);
+        self.emit(EventKind::LimitExceeded { breach, restart });
+        if restart {
+            self.restart_for(RestartReason::Limit(breach)).await;
+        }
+    }
+
+    /// restart program, reason is kept with the exit it causes
+    async fn restart_for(self: &Arc<Self>, reason: RestartReason) {
+        self.info.lock().unwrap().pending_reason = Some(reason);
+        if let Err(e) = self.exec_cmd(mCommand::Restart).await {
+            error!(
+                "restart program {} for {} failed: {e}",
+                self.conf.path, reason
+            );
+        }
+        // not taken if program exited before it is stopped
+        self.info.lock().unwrap().pending_reason = None;
+    }
+
+    /// probe running program every intervalSeconds after startGraceSeconds,
+    /// it is unhealthy after failureThreshold failures in a row
+    async fn check_health(pc: Weak<Self>) {
+        let mut failures = 0;
+        loop {
+            let conf = match pc.upgrade() {
+                Some(pc) => match &pc.health {
+                    Some(conf) => conf.clone(),
+                    None => return,
+                },
+                None => return,
+            };
+            time::sleep(Duration::from_secs(conf.interval)).await;
+            let pc = match pc.upgrade() {
+                Some(pc) => pc,
+                None => return,
+            };
+            let in_grace = match pc.info.lock().unwrap().started_at {
+                Some(at) => at.elapsed() < Duration::from_secs(conf.start_grace),
+                None => true,
+            };
+            let running = matches!(pc.state(), ProcessState::Running | ProcessState::Unhealthy);
+            let pid = match pc.pid() {
+                Some(pid) if running && !in_grace => pid,
+                _ => {
+                    failures = 0;
+                    continue;
+                }
+            };
+
+            let timeout = Duration::from_secs(conf.timeout);
+            match probe::check(&conf.probe, || pc.probe_confinement(pid), timeout).await {
+                Ok(()) => {
+                    failures = 0;
+                    let recovered = pc.state.send_if_modified(|s| {
+                        if *s != ProcessState::Unhealthy {
+                            return false;
+                        }
+                        *s = ProcessState::Running;
+                        true
+                    });
+                    if recovered {
+                        info!("program {} is healthy again", pc.conf.path);
+                        pc.emit(EventKind::Healthy);
+                    }
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        "health probe of program {} failed {}/{}: {e:#}",
+                        pc.conf.path, failures, conf.failure_threshold
+                    );
+                    if failures < conf.failure_threshold {
+                        continue;
+                    }
+                    let turned = pc.state.send_if_modified(|s| {
+                        if *s != ProcessState::Running {
+                            return false;
+                        }
+                        *s = ProcessState::Unhealthy;
+                        true
+                    });
+                    if turned || conf.restart {
+                        pc.emit(EventKind::Unhealthy {
+                            message: format!("{e:#}"),
+                            restart: conf.restart,
+                        });
+                    }
+                    if conf.restart {
+                        failures = 0;
+                        pc.restart_for(RestartReason::Unhealthy).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// restart running program once it sent no WATCHDOG=1 for timeout,
+    /// counted from spawn until its first ping
+    async fn check_watchdog(
+        pc: Weak<Self>,
+        mut messages: broadcast::Receiver<Notification>,
+        timeout: Duration,
+    ) {
+        let mut last_ping = None;
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            tokio::select! {
+                message = messages.recv() => {
+                    match message {
+                        Ok(n) if n.message == "WATCHDOG=1" => {
+                            let running = pc.upgrade().and_then(|pc| pc.pid());
+                            if running.is_some_and(|pid| n.sent_by(pid)) {
+                                last_ping = Some(Instant::now());
+                            }
+                        }
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    }
+                    continue;
+                }
+                _ = time::sleep_until(deadline.into()) => {}
+            }
+            let pc = match pc.upgrade() {
+                Some(pc) => pc,
+                None => return,
+            };
+            let now = Instant::now();
+            // pings of previous run are older than spawn
+            let since = match pc.info.lock().unwrap().started_at {
+                Some(at) => last_ping.map_or(at, |p: Instant| p.max(at)),
+                None => now,
+            };
+            deadline = since + timeout;
+            if deadline > now {
+                continue;
+            }
+            deadline = now + timeout;
+            // starting program is bounded by readiness instead
+            if !matches!(pc.state(), ProcessState::Running | ProcessState::Unhealthy) {
+                continue;
+            }
+            warn!(
+                "program {} sent no watchdog ping in {}s",
+                pc.conf.path,
+                timeout.as_secs()
+            );
+            pc.emit(EventKind::WatchdogTimeout);
+            pc.restart_for(RestartReason::Watchdog).await;
+        }
+    }
+
+    /// turn rotations of logs of this program into events
+    async fn forward_rotated(pc: Weak<Self>, mut rotated: broadcast::Receiver<String>) {
+        loop {
+            let path = match rotated.recv().await {
+                Ok(path) => path,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+            let pc = match pc.upgrade() {
+                Some(pc) => pc,
+                None => return,
+            };
+            if path == pc.log.path || pc.log.stderr_path.as_ref() == Some(&path) {
+                pc.emit(EventKind::Rotated { path });
+            }
+        }
+    }
+
+    /// attaching requires a running program with piped stdin
+    pub fn check_attach(&self) -> Result<()> {
+        if !self.conf.stdin {
+            return Err(Error::InvalidArgument(
+                "stdin of program is not piped, set process.stdin to attach".to_string(),
+            )
+            .into());
+        }
+        self.pid().ok_or(Error::NotRunning)?;
+        Ok(())
+    }
+
+    pub async fn write_stdin(&self, data: &[u8]) -> Result<()> {
+        self.check_attach()?;
+        let mut stdin = self.stdin.lock().await;
+        let pipe = stdin.as_mut().ok_or(Error::NotRunning)?;
+        if let Err(e) = pipe.write_all(data).await {
+            // program closed its stdin or exited
+            *stdin = None;
+            return Err(e.into());
+        }
+        pipe.flush().await?;
         Ok(())
     }
 
+    /// stats of stdout log and stderr log if set
+    pub fn log_stats(&self) -> Vec<(String, Arc<LogStats>)> {
+        let mut paths = vec![self.log.path.clone()];
+        paths.extend(self.log.stderr_path.clone());
+        paths
+            .into_iter()
+            .map(|path| {
+                let stats = self.rotate.stats(&path);
+                (path, stats)
+            })
+            .collect()
+    }
+
+    fn stderr_log(&self) -> Option<Log> {
+        self.log.stderr_path.as_ref().map(|path| Log {
+            path: path.clone(),
+            stderr_path: None,
+            ..self.log.clone()
+        })
+    }
+
+    pub fn status(&self) -> ProgramStatus {
+        let info = self.info.lock().unwrap();
+        ProgramStatus {
+            name: self.name.clone(),
+            state: self.state(),
+            pid: info.pid,
+            uptime: info.started_at.map(|t| t.elapsed().as_secs()),
+            restarts: info.restarts,
+            last_exit: info.last_exit,
+            log_path: self.log.path.clone(),
+            usage: info.usage,
+            last_exit_reason: info.last_exit_reason,
+        }
+    }
+
     fn is_executing(&self) -> bool {
         // TODO: change ordering to relaxed?
-        !self
-            .exec_status
+        self.exec_status
             .compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst)
-            .is_ok()
+            .is_err()
     }
 
-    fn set_executing(&self) {
-        self.exec_status.store(1, Ordering::SeqCst)
+    fn set_idle(&self) {
+        self.exec_status.store(0, Ordering::SeqCst)
     }
 
-    async fn start_cmd(&self) -> Result<()> {
-        if self.is_running().await? {
-            return Ok(());
+    /// spawn program, pid is returned once it is ready if wait_ready is
+    /// set and readiness is configured
+    async fn start_cmd(self: &Arc<Self>, wait_ready: bool) -> Result<u32> {
+        self.stopping.store(false, Ordering::SeqCst);
+        if let Some(pid) = self.pid() {
+            return Ok(pid);
+        }
+        let mut state = self.state.subscribe();
+        let pid = self.spawn().await?;
+        if !wait_ready || self.readiness.is_none() {
+            return Ok(pid);
+        }
+        Self::wait_ready(&mut state).await?;
+        // program may be ready after retries
+        Ok(self.pid().unwrap_or(pid))
+    }
+
+    async fn spawn(self: &Arc<Self>) -> Result<u32> {
+        let (pid, child, ready) = self.launch().await?;
+        let pc = self.clone();
+        tokio::spawn(async move { pc.supervise(child, ready, false).await });
+        Ok(pid)
+    }
+
+    /// start a new instance and gracefully stop the running one once the
+    /// new one is up, the running one is kept if the new one fails
+    async fn replace_cmd(self: &Arc<Self>) -> Result<u32> {
+        self.stopping.store(false, Ordering::SeqCst);
+        let before = match &self.cgroup {
+            Some(cgroup) => cgroup.pids().unwrap_or_default(),
+            None => vec![],
+        };
+        let (pid, mut child, ready) = self.spawn_child().await?;
+        self.emit(EventKind::Started { pid });
+        info!(
+            "program {} started to replace running one, pid is {}",
+            self.conf.path, pid
+        );
+        if let Some(status) = self.wait_up(&mut child, ready).await {
+            let status = status?;
+            warn!(
+                "program {} exited before replacing running one: {}",
+                self.conf.path, status
+            );
+            self.emit(EventKind::Exited {
+                exit: status.into(),
+            });
+            return Err(Error::NotReady(format!("new instance exited: {}", status)).into());
+        }
+
+        *self.stdin.lock().await = child.stdin.take();
+        let old = {
+            let mut info = self.info.lock().unwrap();
+            let old = info.pid.replace(pid);
+            info.started_at = Some(Instant::now());
+            info.usage = None;
+            info.restarts += 1;
+            info.last_exit_reason = info.pending_reason.take();
+            self.state.send_replace(ProcessState::Running);
+            old
+        };
+        let pc = self.clone();
+        tokio::spawn(async move { pc.supervise(child, None, true).await });
+        if let Some(old) = old {
+            self.retire(old, before).await?;
+        }
+        Ok(pid)
+    }
+
+    /// stop instance replaced by a new one as stop does, but cgroup is
+    /// shared with the new one, so only processes of the old one in it are
+    /// killed: those in it before the new one was spawned or in tree of old
+    async fn retire(&self, pid: u32, before: Vec<u32>) -> Result<()> {
+        send_signal(-(pid as i32), stop_signal(self.conf.stop_signal))?;
+        let deadline = Instant::now() + Duration::from_secs(self.conf.stop_interval);
+        while procfs::group_alive(pid) {
+            if Instant::now() >= deadline {
+                warn!(
+                    "replaced program {} not stopped in {}s, killing it",
+                    self.conf.path, self.conf.stop_interval
+                );
+                send_signal(-(pid as i32), libc::SIGKILL)?;
+                break;
+            }
+            time::sleep(RETIRE_POLL_INTERVAL).await;
+        }
+
+        let cgroup = match &self.cgroup {
+            Some(cgroup) => cgroup,
+            None => return Ok(()),
+        };
+        match cgroup.pids() {
+            Ok(pids) => {
+                for p in pids {
+                    if before.contains(&p) || procfs::in_tree(p, pid) {
+                        send_signal(p as i32, libc::SIGKILL)?;
+                    }
+                }
+            }
+            Err(e) => debug!("{e:#}"),
+        }
+        Ok(())
+    }
+
+    /// spawn program and take it as the running instance
+    async fn launch(&self) -> Result<(u32, Child, Option<ReadyWait>)> {
+        let (pid, mut child, ready) = self.spawn_child().await?;
+        *self.stdin.lock().await = child.stdin.take();
+        {
+            let mut info = self.info.lock().unwrap();
+            info.pid = Some(pid);
+            info.started_at = Some(Instant::now());
+            self.state.send_replace(ProcessState::Starting);
+        }
+        self.emit(EventKind::Started { pid });
+        info!("program {} started, pid is {}", self.conf.path, pid);
+        Ok((pid, child, ready))
+    }
+
+    fn confinement(&self) -> Result<Confinement> {
+        Confinement::new(&self.conf, self.cgroup.clone())
+    }
+
+    /// confinement of exec probes of program running as pid
+    fn probe_confinement(&self, pid: u32) -> Result<Confinement> {
+        Confinement::probe(&self.conf, pid)
+    }
+
+    /// readiness is prepared before program is spawned so that no output or
+    /// notification of it is missed
+    async fn spawn_child(&self) -> Result<(u32, Child, Option<ReadyWait>)> {
+        let confinement = self
+            .confinement()
+            .map_err(|e| Error::SpawnFailed(format!("{}: {:#}", self.conf.path, e)))?;
+        // bind error is logged when controller is created
+        if self.conf.watchdog.is_some() && self.notify.is_none() {
+            return Err(Error::SpawnFailed(format!(
+                "{}: notify socket for watchdog is not available",
+                self.conf.path
+            ))
+            .into());
+        }
+        let mut cmd = Command::new(&self.conf.path);
+        cmd.stdin(if self.conf.stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+        if let Some(args) = &self.conf.args {
+            cmd.args(args);
+        }
+        let cred = confinement.credential();
+        let owner = cred.owner();
+        // envs of config take precedence over env file, and both over
+        // those of user
+        if self.conf.clean_env {
+            cmd.env_clear();
+        }
+        cmd.envs(cred.envs());
+        if let Some(path) = &self.conf.env_file {
+            cmd.envs(
+                env_file::read(path)
+                    .map_err(|e| Error::SpawnFailed(format!("{}: {:#}", self.conf.path, e)))?,
+            );
+        }
+        if let Some(envs) = &self.conf.envs {
+            cmd.envs(envs);
+        }
+        if let Some(notify) = &self.notify {
+            cmd.env("NOTIFY_SOCKET", notify.path());
+            if let Some(watchdog) = self.conf.watchdog {
+                cmd.env("WATCHDOG_USEC", (watchdog * 1_000_000).to_string());
+            }
+        }
+        // output of this instance only, so output of the instance it replaces
+        // can't make it ready, notifications are told apart by pid instead
+        let (output, _) = broadcast::channel(ATTACH_CHANNEL_LENGTH);
+        let ready = match &self.readiness {
+            Some(conf) => Some(
+                ReadyWait::prepare(conf, &output, self.notify.as_ref())
+                    .map_err(|e| Error::SpawnFailed(format!("{}: {}", self.conf.path, e)))?,
+            ),
+            None => None,
+        };
+        let exec = match self.sockets.is_empty() {
+            true => None,
+            false => Some(
+                self.sockets
+                    .exec(cmd.as_std(), self.conf.clean_env)
+                    .map_err(|e| Error::SpawnFailed(format!("{}: {}", self.conf.path, e)))?,
+            ),
+        };
+        confinement.confine(&mut cmd, exec);
+
+        let writer = LogWriter::open(self.log.clone(), self.rotate.clone(), owner).await?;
+        let stderr_writer = match self.stderr_log() {
+            Some(log) => Some(LogWriter::open(log, self.rotate.clone(), owner).await?),
+            None => None,
+        };
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::SpawnFailed(format!("{}: {}", self.conf.path, e)))?;
+        let pid = child.id().ok_or_else(|| {
+            Error::SpawnFailed(format!("{}: exited before getting pid", self.conf.path))
+        })?;
+
+        let writer = Arc::new(AsyncMutex::new(writer));
+        let stderr_writer = match stderr_writer {
+            Some(w) => Arc::new(AsyncMutex::new(w)),
+            None => writer.clone(),
+        };
+        let outputs = vec![self.attached.clone(), output];
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(LogWriter::copy(stdout, writer, outputs.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(LogWriter::copy(stderr, stderr_writer, outputs));
         }
+        Ok((pid, child, ready))
+    }
+
+    /// wait for program to exit and restart it according to restart strategy,
+    /// program exited in startSeconds, or before it is ready if readiness is
+    /// set, is retried with increasing delay. up is set for program already
+    /// up, such as one replacing the running one
+    async fn supervise(
+        self: Arc<Self>,
+        mut child: Child,
+        mut ready: Option<ReadyWait>,
+        mut up: bool,
+    ) {
+        let mut retries = 0;
+        loop {
+            let pid = child.id();
+            let exited_in_starting = match up {
+                true => None,
+                false => self.wait_up(&mut child, ready.take()).await,
+            };
+            up = false;
+            let (status, running) = match exited_in_starting {
+                Some(status) => (status, false),
+                None => {
+                    self.state.send_if_modified(|s| {
+                        if *s != ProcessState::Starting {
+                            return false;
+                        }
+                        *s = ProcessState::Running;
+                        true
+                    });
+                    retries = 0;
+                    (child.wait().await, true)
+                }
+            };
+
+            let status = match status {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("wait program {} failed: {e}", self.conf.path);
+                    return;
+                }
+            };
+            if pid.is_some() && self.pid() != pid {
+                info!("replaced program {} exited: {}", self.conf.path, status);
+                self.info.lock().unwrap().last_exit = Some(status.into());
+                return;
+            }
+            *self.stdin.lock().await = None;
+            let next = {
+                let mut info = self.info.lock().unwrap();
+                info.pid = None;
+                info.started_at = None;
+                info.usage = None;
+                info.last_exit = Some(status.into());
+                info.last_exit_reason = info.pending_reason.take();
+                let next = if self.stopping.load(Ordering::SeqCst) {
+                    ProcessState::Stopped
+                } else if running {
+                    ProcessState::Exited
+                } else if retries < self.conf.start_retries {
+                    ProcessState::Backoff
+                } else {
+                    ProcessState::Fatal
+                };
+                self.state.send_replace(next);
+                next
+            };
+            self.emit(EventKind::Exited {
+                exit: status.into(),
+            });
+
+            let delay = match next {
+                ProcessState::Stopped => {
+                    info!("program {} stopped", self.conf.path);
+                    return;
+                }
+                ProcessState::Exited => {
+                    warn!("program {} exited: {}", self.conf.path, status);
+                    if !self.should_restart(status) {
+                        return;
+                    }
+                    RESTART_INTERVAL
+                }
+                ProcessState::Backoff => {
+                    retries += 1;
+                    warn!(
+                        "program {} exited while starting: {}, retry {}/{}",
+                        self.conf.path, status, retries, self.conf.start_retries
+                    );
+                    let delay = RESTART_INTERVAL * retries;
+                    self.emit(EventKind::Backoff {
+                        retries,
+                        delay: delay.as_secs(),
+                    });
+                    delay
+                }
+                _ => {
+                    error!(
+                        "program {} failed to start after {} retries",
+                        self.conf.path, retries
+                    );
+                    self.emit(EventKind::Fatal);
+                    return;
+                }
+            };
 
+            time::sleep(delay).await;
+            if self.stopping.load(Ordering::SeqCst) || self.pid().is_some() {
+                return;
+            }
+            (child, ready) = match self.launch().await {
+                Ok((_, child, next)) => (child, next),
+                Err(e) => {
+                    error!("restart program {} failed: {e}", self.conf.path);
+                    self.state.send_replace(ProcessState::Fatal);
+                    self.emit(EventKind::Fatal);
+                    return;
+                }
+            };
+            self.info.lock().unwrap().restarts += 1;
+        }
+    }
+
+    /// wait for program to run for startSeconds, or to be ready if
+    /// readiness is set, exit is returned if it exited meanwhile
+    async fn wait_up(
+        &self,
+        child: &mut Child,
+        ready: Option<ReadyWait>,
+    ) -> Option<io::Result<ExitStatus>> {
+        match ready {
+            Some(ready) => self.wait_started(child, ready).await,
+            None => tokio::select! {
+                status = child.wait() => Some(status),
+                _ = time::sleep(Duration::from_secs(self.conf.start_interval)) => None,
+            },
+        }
+    }
+
+    /// wait for program to be ready in timeoutSeconds, program not ready in
+    /// time is killed and its exit is returned as if it exited while starting
+    async fn wait_started(
+        &self,
+        child: &mut Child,
+        ready: ReadyWait,
+    ) -> Option<io::Result<ExitStatus>> {
+        let conf = self.readiness.as_ref()?;
+        let timeout = Duration::from_secs(conf.timeout);
+        // child is not waited yet, so its pid is known
+        let pid = child.id().unwrap_or_default();
+        let ready = time::timeout(
+            timeout,
+            ready.wait(
+                pid,
+                || self.probe_confinement(pid),
+                Duration::from_secs(conf.interval),
+            ),
+        );
+        let ready = tokio::select! {
+            status = child.wait() => return Some(status),
+            ready = ready => ready,
+        };
+        if ready.is_ok() {
+            info!("program {} is ready", self.conf.path);
+            return None;
+        }
+        warn!(
+            "program {} not ready in {}s, killing it",
+            self.conf.path, conf.timeout
+        );
+        if let Some(pid) = child.id() {
+            // cgroup is shared with the running instance while replacing it
+            let killed = match self.pid() == Some(pid) {
+                true => self.kill_all(pid),
+                false => send_signal(-(pid as i32), libc::SIGKILL),
+            };
+            if let Err(e) = killed {
+                error!("kill program {} failed: {e}", self.conf.path);
+            }
+        }
+        Some(child.wait().await)
+    }
+
+    fn should_restart(&self, status: ExitStatus) -> bool {
+        match self.conf.restart_strategy {
+            ProcessRestartStrategy::Always => true,
+            ProcessRestartStrategy::OnFailure => !status.success(),
+            ProcessRestartStrategy::AlwaysNot => false,
+        }
+    }
+
+    async fn stop_cmd(&self) -> Result<()> {
+        let mut state = self.state.subscribe();
+        let pid = match self.mark_stopping() {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+
+        send_signal(pid as i32, stop_signal(self.conf.stop_signal))?;
+        let stop_interval = Duration::from_secs(self.conf.stop_interval);
+        if time::timeout(stop_interval, Self::wait_stopped(&mut state))
+            .await
+            .is_err()
+        {
+            warn!(
+                "program {} not stopped in {}s, killing it",
+                self.conf.path, self.conf.stop_interval
+            );
+            self.kill_all(pid)?;
+            Self::wait_stopped(&mut state).await;
+        }
+        self.kill_leftovers();
         Ok(())
     }
 
-    async fn is_running(&self) -> Result<bool> {
-        Ok(false)
+    /// stop program and remove its cgroup, processes killed in cgroup may
+    /// take a moment to leave it
+    async fn exit_cmd(&self) -> Result<()> {
+        self.stop_cmd().await?;
+        if let Some(cgroup) = &self.cgroup {
+            let deadline = Instant::now() + CGROUP_REMOVE_TIMEOUT;
+            while let Err(e) = cgroup.remove() {
+                if Instant::now() >= deadline {
+                    warn!("{e:#}");
+                    break;
+                }
+                time::sleep(RETIRE_POLL_INTERVAL).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn kill_cmd(&self) -> Result<()> {
+        let mut state = self.state.subscribe();
+        let pid = match self.mark_stopping() {
+            Some(pid) => pid,
+            None => return Ok(()),
+        };
+
+        self.kill_all(pid)?;
+        Self::wait_stopped(&mut state).await;
+        self.kill_leftovers();
+        Ok(())
+    }
+
+    /// kill cgroup of program, or its process group without cgroup
+    fn kill_all(&self, pid: u32) -> Result<()> {
+        if let Some(cgroup) = &self.cgroup {
+            match cgroup.kill() {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("{e:#}, kill process group instead"),
+            }
+        }
+        send_signal(-(pid as i32), libc::SIGKILL)
+    }
+
+    /// descendants left their process group may outlive program, they are
+    /// only found in its cgroup
+    fn kill_leftovers(&self) {
+        if let Some(cgroup) = &self.cgroup {
+            if let Err(e) = cgroup.kill() {
+                debug!("{e:#}");
+            }
+        }
+    }
+
+    /// disable restart and return pid of program if it is still running
+    fn mark_stopping(&self) -> Option<u32> {
+        let info = self.info.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+        match info.pid {
+            Some(pid) => {
+                self.state.send_replace(ProcessState::Stopping);
+                Some(pid)
+            }
+            None => {
+                self.state.send_replace(ProcessState::Stopped);
+                None
+            }
+        }
+    }
+
+    fn reload_cmd(&self) -> Result<u32> {
+        let pid = self.pid().ok_or(Error::NotRunning)?;
+        send_signal(pid as i32, libc::SIGHUP)?;
+        self.emit(EventKind::Reloaded);
+        Ok(pid)
+    }
+
+    /// wait until program is running, retries in backoff included
+    async fn wait_ready(state: &mut watch::Receiver<ProcessState>) -> Result<()> {
+        loop {
+            let s = *state.borrow_and_update();
+            match s {
+                ProcessState::Running | ProcessState::Unhealthy => return Ok(()),
+                ProcessState::Stopped | ProcessState::Exited | ProcessState::Fatal => {
+                    return Err(Error::NotReady(format!("program is {}", s)).into())
+                }
+                _ => {}
+            }
+            if state.changed().await.is_err() {
+                return Err(Error::NotReady("program is dropped".to_string()).into());
+            }
+        }
+    }
+
+    async fn wait_stopped(state: &mut watch::Receiver<ProcessState>) {
+        while *state.borrow_and_update() != ProcessState::Stopped {
+            if state.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// uid and gid program runs as, none if it is unknown
+fn owner_of(conf: &Process) -> (Option<u32>, Option<u32>) {
+    Credential::lookup(conf.user.as_deref(), conf.group.as_deref())
+        .map(|cred| cred.owner())
+        .unwrap_or_default()
+}
+
+fn stop_signal(s: StopSignal) -> libc::c_int {
+    match s {
+        StopSignal::Term => libc::SIGTERM,
+        StopSignal::Int => libc::SIGINT,
+        StopSignal::Quit => libc::SIGQUIT,
+        StopSignal::Hup => libc::SIGHUP,
+        StopSignal::Kill => libc::SIGKILL,
+        StopSignal::Usr1 => libc::SIGUSR1,
+        StopSignal::Usr2 => libc::SIGUSR2,
+    }
+}
+
+/// negative pid sends signal to the process group
+fn send_signal(pid: i32, sig: libc::c_int) -> Result<()> {
+    if unsafe { libc::kill(pid, sig) } != 0 {
+        let e = io::Error::last_os_error();
+        // process already gone
+        if e.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        return Err(Error::SignalFailed(format!("signal {} to {}: {}", sig, pid, e)).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{controller::error::ErrorKind, rotater::rotater::Rotater};
+
+    // work dir of a test, removed on drop so a failed test cleans up too
+    struct Fixture {
+        dir: std::path::PathBuf,
+        rotater: Rotater,
+    }
+
+    impl Fixture {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "sup-controller-{}-{}",
+                test,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self {
+                dir,
+                rotater: Rotater::new(1).unwrap(),
+            }
+        }
+
+        fn program(&self, process: &str) -> Program {
+            let mut p: Program = toml::from_str(&format!(
+                "name = \"test\"
+[process]
+workDir = \"{}\"
+{}
+[log]
+path = \"{}/test.log\"
+",
+                self.dir.display(),
+                process,
+                self.dir.display()
+            ))
+            .unwrap();
+            p.process.work_dir = self.dir.to_str().unwrap().to_string();
+            p
+        }
+
+        fn controller(&self, conf: Program) -> Arc<ProcessController> {
+            ProcessController::new(conf, Sockets::default(), None, self.rotater.handle())
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // states may be coalesced by watch, events are not
+    async fn events_until(events: &mut broadcast::Receiver<Event>, n: usize) -> Vec<EventKind> {
+        let mut seen = vec![];
+        while seen.len() < n {
+            let event = time::timeout(Duration::from_secs(30), events.recv())
+                .await
+                .expect("timed out waiting for events")
+                .unwrap();
+            seen.push(event.kind);
+        }
+        seen
+    }
+
+    fn names(kinds: &[EventKind]) -> Vec<&'static str> {
+        kinds.iter().map(EventKind::name).collect()
+    }
+
+    #[tokio::test]
+    async fn backoff_to_fatal_test() {
+        let f = Fixture::new("fatal");
+        let pc = f.controller(f.program(
+            "path = \"/bin/false\"\nautoStart = true\nstartSeconds = 1\nstartRetries = 1",
+        ));
+        let mut events = pc.subscribe_events();
+        pc.auto_start().await.unwrap();
+
+        assert_eq!(
+            names(&events_until(&mut events, 6).await),
+            vec!["started", "exited", "backoff", "started", "exited", "fatal"]
+        );
+        let st = pc.status();
+        assert_eq!(st.state, ProcessState::Fatal);
+        assert_eq!(st.pid, None);
+        assert_eq!(st.restarts, 1);
+        assert_eq!(st.last_exit, Some(ExitReason::Code(1)));
+    }
+
+    #[tokio::test]
+    async fn restart_on_limit_test() {
+        let f = Fixture::new("limit");
+        let pc = f.controller(f.program(
+            "path = \"/bin/sleep\"\nargs = [\"10\"]\nautoStart = true\nmaxRss = 1\nlimitGraceSeconds = 0",
+        ));
+        let mut events = pc.subscribe_events();
+        pc.auto_start().await.unwrap();
+
+        let seen = events_until(&mut events, 4).await;
+        match &seen[1] {
+            EventKind::LimitExceeded { breach, restart } => {
+                assert_eq!(breach.limit, Limit::Rss);
+                assert!(restart);
+            }
+            kind => panic!("unexpected event {:?}", kind),
+        }
+        assert_eq!(names(&seen[2..]), vec!["exited", "started"]);
+        assert!(matches!(
+            pc.status().last_exit_reason,
+            Some(RestartReason::Limit(LimitBreach {
+                limit: Limit::Rss,
+                ..
+            }))
+        ));
+        pc.exec_cmd(mCommand::Stop).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_unhealthy_test() {
+        let f = Fixture::new("health");
+        let mut conf =
+            f.program("path = \"/bin/sleep\"\nargs = [\"10\"]\nautoStart = true\nstartSeconds = 0");
+        conf.health = Some(
+            toml::from_str(
+                "type = \"exec\"\ncommand = [\"/bin/false\"]\nintervalSeconds = 1\nfailureThreshold = 1\nrestart = true",
+            )
+            .unwrap(),
+        );
+        let pc = f.controller(conf);
+        let mut events = pc.subscribe_events();
+        pc.auto_start().await.unwrap();
+
+        assert_eq!(
+            names(&events_until(&mut events, 4).await),
+            vec!["started", "unhealthy", "exited", "started"]
+        );
+        assert_eq!(pc.status().last_exit_reason, Some(RestartReason::Unhealthy));
+        pc.exec_cmd(mCommand::Stop).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_ready_test() {
+        let f = Fixture::new("ready");
+        let mut conf = f.program(
+            "path = \"/bin/sh\"\nargs = [\"-c\", \"sleep 1; echo listening; sleep 10\"]\nstartSeconds = 0",
+        );
+        conf.readiness = Some(
+            toml::from_str("type = \"stdout\"\npattern = \"^listening\"\ntimeoutSeconds = 5")
+                .unwrap(),
+        );
+        let pc = f.controller(conf);
+        let at = Instant::now();
+        pc.exec_cmd(mCommand::Start).await.unwrap();
+        assert!(at.elapsed() >= Duration::from_secs(1));
+        assert_eq!(pc.state(), ProcessState::Running);
+        pc.exec_cmd(mCommand::Stop).await.unwrap();
+
+        // program never ready is killed and fails to start
+        let mut conf = f.program("path = \"/bin/sleep\"\nargs = [\"10\"]\nstartRetries = 0");
+        conf.readiness =
+            Some(toml::from_str("type = \"file\"\npath = \"ready\"\ntimeoutSeconds = 1").unwrap());
+        let pc = f.controller(conf);
+        let e = pc.exec_cmd(mCommand::Start).await.unwrap_err();
+        assert_eq!(ErrorKind::from(&e), ErrorKind::NotReady);
+        assert_eq!(pc.state(), ProcessState::Fatal);
+    }
+
+    #[tokio::test]
+    async fn restart_on_watchdog_test() {
+        let f = Fixture::new("watchdog");
+        // pings for 2s, then goes quiet
+        let pc = f.controller(f.program(
+            "path = \"/usr/bin/perl\"
+args = [\"-MSocket\", \"-e\", \"socket(my $s, AF_UNIX, SOCK_DGRAM, 0); for (1..4) { send($s, 'WATCHDOG=1', 0, pack_sockaddr_un($ENV{NOTIFY_SOCKET})); select(undef, undef, undef, 0.5) } sleep 10\"]
+startSeconds = 0
+watchdogSeconds = 1",
+        ));
+        let mut events = pc.subscribe_events();
+        pc.exec_cmd(mCommand::Start).await.unwrap();
+        time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(pc.status().restarts, 0);
+
+        // pings of anyone else don't keep program running
+        let notify = pc.notify.as_ref().unwrap().path().to_path_buf();
+        let done = Arc::new(AtomicBool::new(false));
+        let stranger = std::thread::spawn({
+            let done = done.clone();
+            move || {
+                let client = std::os::unix::net::UnixDatagram::unbound().unwrap();
+                while !done.load(Ordering::SeqCst) {
+                    let _ = client.send_to(b"WATCHDOG=1", &notify);
+                    std::thread::sleep(Duration::from_millis(250));
+                }
+            }
+        });
+        let seen = time::timeout(Duration::from_secs(4), events_until(&mut events, 4)).await;
+        done.store(true, Ordering::SeqCst);
+        stranger.join().unwrap();
+        let seen = seen.expect("program kept running without pings");
+        assert_eq!(
+            names(&seen),
+            vec!["started", "watchdog_timeout", "exited", "started"]
+        );
+        assert_eq!(pc.status().last_exit_reason, Some(RestartReason::Watchdog));
+        pc.exec_cmd(mCommand::Stop).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restart_start_first_test() {
+        let f = Fixture::new("replace");
+        // running instance keeps printing ready, new one is ready after 1s,
+        // background sleep is in process group of its instance
+        let mut conf = f.program(
+            "path = \"/bin/sh\"
+args = [\"-c\", \"sleep 30 & echo $! > bg.$$; sleep 1; while echo ready; do sleep 0.2; done\"]
+restartMode = \"start-first\"
+startSeconds = 0",
+        );
+        conf.readiness = Some(
+            toml::from_str("type = \"stdout\"\npattern = \"^ready\"\ntimeoutSeconds = 5").unwrap(),
+        );
+        let pc = f.controller(conf);
+        let old = pc.exec_cmd(mCommand::Start).await.unwrap().unwrap();
+        let mut events = pc.subscribe_events();
+        let at = Instant::now();
+        let new = pc.exec_cmd(mCommand::Restart).await.unwrap().unwrap();
+        assert!(at.elapsed() >= Duration::from_secs(1));
+        assert_ne!(old, new);
+
+        // whole process group of replaced instance is stopped
+        let bg: i32 = std::fs::read_to_string(f.dir.join(format!("bg.{}", old)))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let alive = |pid: i32| {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        assert!(!alive(old as i32));
+        assert!(!alive(bg));
+
+        // exit of replaced instance is not an exit of program
+        assert_eq!(events.try_recv().unwrap().kind.name(), "started");
+        assert!(events.try_recv().is_err());
+        let st = pc.status();
+        assert_eq!(st.state, ProcessState::Running);
+        assert_eq!(st.pid, Some(new));
+        pc.exec_cmd(mCommand::Stop).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn env_file_test() {
+        let f = Fixture::new("env");
+        std::fs::write(f.dir.join("run.env"), "A=file\nB=file\n").unwrap();
+        let pc = f.controller(f.program(&format!(
+            "path = \"/bin/sh\"\nargs = [\"-c\", \"echo $A $B ${{HOME:-none}}\"]\nenvFile = \"{}/run.env\"\ncleanEnv = true\nrestartStrategy = \"none\"\nstartSeconds = 0\n[process.envs]\nB = \"envs\"",
+            f.dir.display()
+        )));
+        let mut events = pc.subscribe_events();
+        pc.exec_cmd(mCommand::Start).await.unwrap();
+        while events.recv().await.unwrap().kind.name() != "exited" {}
+        // output may be copied to log after exit is seen
+        let read_log = || std::fs::read_to_string(f.dir.join("test.log")).unwrap_or_default();
+        while read_log().is_empty() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(read_log(), "file envs none\n");
     }
 }
@@ -0,0 +1,210 @@
+use std::{
+    ffi::{CStr, CString},
+    io,
+};
+
+use anyhow::{anyhow, Result};
+
+use super::sys::check;
+
+const LOOKUP_BUFFER_SIZE: usize = 16384;
+
+/// user and group program runs as, looked up by sup and applied in program
+/// by pre_exec
+#[derive(Debug, Default)]
+pub struct Credential {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    // supplementary groups, set along with gid
+    groups: Vec<libc::gid_t>,
+    // name and home directory of user
+    user: Option<(String, String)>,
+}
+
+struct Passwd {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    home: String,
+}
+
+impl Credential {
+    /// group defaults to primary group of user, switching to another user
+    /// needs sup to run as root
+    pub fn lookup(user: Option<&str>, group: Option<&str>) -> Result<Self> {
+        let passwd = user.map(|u| lookup_user(u).map(|p| (u, p))).transpose()?;
+        let gid = match (group, &passwd) {
+            (Some(group), _) => Some(lookup_group(group)?),
+            (None, Some((_, p))) => Some(p.gid),
+            (None, None) => None,
+        };
+        let mut cred = Self {
+            uid: passwd.as_ref().map(|(_, p)| p.uid),
+            gid,
+            ..Default::default()
+        };
+        if let Some((name, p)) = passwd {
+            cred.user = Some((name.to_string(), p.home));
+        }
+
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid != 0 {
+            if cred.uid.unwrap_or(euid) != euid || cred.gid.unwrap_or(egid) != egid {
+                return Err(anyhow!(
+                    "run as user {} group {} needs root",
+                    user.unwrap_or("-"),
+                    group.unwrap_or("-")
+                ));
+            }
+            // already running as them
+            cred.uid = None;
+            cred.gid = None;
+            return Ok(cred);
+        }
+
+        cred.groups = match (&cred.user, cred.gid) {
+            (Some((name, _)), Some(gid)) => group_list(name, gid)?,
+            (None, Some(gid)) => vec![gid],
+            _ => vec![],
+        };
+        Ok(cred)
+    }
+
+    /// HOME, USER and LOGNAME of user
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        match &self.user {
+            Some((name, home)) => vec![
+                ("HOME", home.clone()),
+                ("USER", name.clone()),
+                ("LOGNAME", name.clone()),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// owner of files created for program
+    pub fn owner(&self) -> (Option<u32>, Option<u32>) {
+        (self.uid, self.gid)
+    }
+
+    /// called in forked child, groups are dropped before user
+    pub fn apply(&self) -> io::Result<()> {
+        if self.gid.is_some() {
+            check(unsafe { libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) })?;
+        }
+        if let Some(gid) = self.gid {
+            check(unsafe { libc::setgid(gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { libc::setuid(uid) })?;
+        }
+        Ok(())
+    }
+}
+
+fn lookup_user(name: &str) -> Result<Passwd> {
+    let cname = CString::new(name)?;
+    let mut buf = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(anyhow!(
+            "look up user {} failed: {}",
+            name,
+            io::Error::from_raw_os_error(rc)
+        ));
+    }
+    if result.is_null() {
+        return Err(anyhow!("user {} does not exist", name));
+    }
+    Ok(Passwd {
+        uid: pwd.pw_uid,
+        gid: pwd.pw_gid,
+        home: unsafe { CStr::from_ptr(pwd.pw_dir) }
+            .to_string_lossy()
+            .to_string(),
+    })
+}
+
+fn lookup_group(name: &str) -> Result<libc::gid_t> {
+    let cname = CString::new(name)?;
+    let mut buf = vec![0 as libc::c_char; LOOKUP_BUFFER_SIZE];
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            cname.as_ptr(),
+            &mut grp,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(anyhow!(
+            "look up group {} failed: {}",
+            name,
+            io::Error::from_raw_os_error(rc)
+        ));
+    }
+    if result.is_null() {
+        return Err(anyhow!("group {} does not exist", name));
+    }
+    Ok(grp.gr_gid)
+}
+
+/// groups user is member of, gid is always included
+fn group_list(name: &str, gid: libc::gid_t) -> Result<Vec<libc::gid_t>> {
+    let cname = CString::new(name)?;
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        let rc = unsafe { libc::getgrouplist(cname.as_ptr(), gid, groups.as_mut_ptr(), &mut n) };
+        if rc >= 0 {
+            groups.truncate(n as usize);
+            return Ok(groups);
+        }
+        // n is set to the number of groups needed
+        groups.resize((n as usize).max(groups.len() * 2), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn credential_test() {
+        let e = Credential::lookup(Some("no-such-user-of-sup"), None).unwrap_err();
+        assert_eq!(e.to_string(), "user no-such-user-of-sup does not exist");
+        assert!(Credential::lookup(None, Some("no-such-group-of-sup")).is_err());
+
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let cred = Credential::lookup(Some("nobody"), None).unwrap();
+        let nobody = lookup_user("nobody").unwrap();
+        assert_eq!(cred.owner(), (Some(nobody.uid), Some(nobody.gid)));
+        assert_eq!(cred.envs()[1], ("USER", "nobody".to_string()));
+
+        let mut cmd = std::process::Command::new("/bin/sh");
+        cmd.args(["-c", "id -u; id -g; id -G"]);
+        unsafe {
+            cmd.pre_exec(move || cred.apply());
+        }
+        let out = cmd.output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out.stdout),
+            format!("{0}\n{1}\n{1}\n", nobody.uid, nobody.gid)
+        );
+    }
+}
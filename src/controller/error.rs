@@ -0,0 +1,56 @@
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum Error {
+    #[error("another command is executing")]
+    Busy,
+    #[error("program is not running")]
+    NotRunning,
+    #[error("spawn program failed: [{0}]")]
+    SpawnFailed(String),
+    #[error("send signal failed: [{0}]")]
+    SignalFailed(String),
+    #[error("invalid argument: [{0}]")]
+    InvalidArgument(String),
+    #[error("program is not ready: [{0}]")]
+    NotReady(String),
+}
+
+/// kind of error carried by response
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Busy,
+    NotRunning,
+    SpawnFailed,
+    SignalFailed,
+    InvalidArgument,
+    NotReady,
+    BadRequest,
+    IncompatibleVersion,
+    TooManyConnections,
+    Internal,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Busy => ErrorKind::Busy,
+            Self::NotRunning => ErrorKind::NotRunning,
+            Self::SpawnFailed(_) => ErrorKind::SpawnFailed,
+            Self::SignalFailed(_) => ErrorKind::SignalFailed,
+            Self::InvalidArgument(_) => ErrorKind::InvalidArgument,
+            Self::NotReady(_) => ErrorKind::NotReady,
+        }
+    }
+}
+
+impl From<&anyhow::Error> for ErrorKind {
+    fn from(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<Error>() {
+            Some(e) => e.kind(),
+            None => ErrorKind::Internal,
+        }
+    }
+}
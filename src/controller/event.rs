@@ -0,0 +1,174 @@
+use std::fmt::Display;
+
+use chrono::{SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::config::EventName;
+
+use super::controller::{ExitReason, LimitBreach};
+
+/// change of program observed by the daemon, streamed to subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    // rfc3339 utc time the event happened
+    pub timestamp: String,
+    pub program: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    Started {
+        pid: u32,
+    },
+    Exited {
+        exit: ExitReason,
+    },
+    // program exited while starting and is retried after delay seconds
+    Backoff {
+        retries: u32,
+        delay: u64,
+    },
+    Fatal,
+    Rotated {
+        path: String,
+    },
+    Reloaded,
+    // usage stayed above limit, program is restarted if restart is set
+    LimitExceeded {
+        #[serde(flatten)]
+        breach: LimitBreach,
+        restart: bool,
+    },
+    // health probes failed, program is restarted if restart is set
+    Unhealthy {
+        message: String,
+        restart: bool,
+    },
+    Healthy,
+    // no WATCHDOG=1 in watchdogSeconds, program is restarted
+    WatchdogTimeout,
+}
+
+impl Event {
+    pub fn new(program: &str, kind: EventKind) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            program: program.to_string(),
+            kind,
+        }
+    }
+}
+
+impl EventKind {
+    /// name of kind as tagged in json
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "started",
+            Self::Exited { .. } => "exited",
+            Self::Backoff { .. } => "backoff",
+            Self::Fatal => "fatal",
+            Self::Rotated { .. } => "rotated",
+            Self::Reloaded => "reloaded",
+            Self::LimitExceeded { .. } => "limit_exceeded",
+            Self::Unhealthy { .. } => "unhealthy",
+            Self::Healthy => "healthy",
+            Self::WatchdogTimeout => "watchdog_timeout",
+        }
+    }
+
+    /// kind as listeners subscribe to it in config
+    pub fn event_name(&self) -> EventName {
+        match self {
+            Self::Started { .. } => EventName::Started,
+            Self::Exited { .. } => EventName::Exited,
+            Self::Backoff { .. } => EventName::Backoff,
+            Self::Fatal => EventName::Fatal,
+            Self::Rotated { .. } => EventName::Rotated,
+            Self::Reloaded => EventName::Reloaded,
+            Self::LimitExceeded { .. } => EventName::LimitExceeded,
+            Self::Unhealthy { .. } => EventName::Unhealthy,
+            Self::Healthy => EventName::Healthy,
+            Self::WatchdogTimeout => EventName::WatchdogTimeout,
+        }
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ", self.timestamp, self.program)?;
+        match &self.kind {
+            EventKind::Started { pid } => write!(f, "started, pid is {}", pid),
+            EventKind::Exited {
+                exit: ExitReason::Code(c),
+            } => write!(f, "exited with code {}", c),
+            EventKind::Exited {
+                exit: ExitReason::Signal(s),
+            } => write!(f, "exited by signal {}", s),
+            EventKind::Backoff { retries, delay } => {
+                write!(f, "backoff, retry {} in {}s", retries, delay)
+            }
+            EventKind::Fatal => write!(f, "fatal"),
+            EventKind::Rotated { path } => write!(f, "rotated {}", path),
+            EventKind::Reloaded => write!(f, "reloaded"),
+            EventKind::LimitExceeded { breach, restart } => {
+                write!(f, "exceeded limit, {}", breach)?;
+                if *restart {
+                    write!(f, ", restarting")?;
+                }
+                Ok(())
+            }
+            EventKind::Unhealthy { message, restart } => {
+                write!(f, "unhealthy, {}", message)?;
+                if *restart {
+                    write!(f, ", restarting")?;
+                }
+                Ok(())
+            }
+            EventKind::Healthy => write!(f, "healthy"),
+            EventKind::WatchdogTimeout => write!(f, "watchdog timeout, restarting"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_json_test() {
+        let event = Event {
+            timestamp: "2023-03-17T20:07:00.000Z".to_string(),
+            program: "run".to_string(),
+            kind: EventKind::Exited {
+                exit: ExitReason::Code(1),
+            },
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"timestamp":"2023-03-17T20:07:00.000Z","program":"run","kind":"exited","exit":{"code":1}}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&json).unwrap(), event);
+        assert_eq!(
+            event.to_string(),
+            "2023-03-17T20:07:00.000Z run exited with code 1"
+        );
+
+        // names in config are those in json
+        for kind in [
+            event.kind,
+            EventKind::Fatal,
+            EventKind::Reloaded,
+            EventKind::Healthy,
+            EventKind::WatchdogTimeout,
+        ] {
+            assert_eq!(
+                serde_json::from_value::<EventName>(kind.name().into()).unwrap(),
+                kind.event_name()
+            );
+        }
+    }
+}
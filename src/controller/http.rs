@@ -0,0 +1,385 @@
+use std::{
+    convert::Infallible,
+    future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    task::Poll,
+};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{
+    body::Bytes,
+    header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    server::conn::Http as HttpConn,
+    service::service_fn,
+    Body, Method, Request as HttpRequest, Response as HttpResponse, StatusCode,
+};
+use log::{error, info, warn};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener, UnixStream},
+    sync::{watch, OwnedSemaphorePermit},
+    time,
+};
+
+use crate::config::config::Http;
+
+use super::{
+    command::{Command, Request, Response, ResponseBody},
+    error::ErrorKind,
+    metrics,
+    server::{Server, MAX_CONNECTIONS, READ_TIMEOUT, TAIL_POLL_INTERVAL, WRITE_TIMEOUT},
+    tail::LogTail,
+};
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+/// exposes commands of control socket as rest endpoints:
+///
+/// - `GET /api/status`
+/// - `POST /api/{start,stop,restart,kill,reload,rotate}`
+/// - `GET /api/tail?lines=10&stderr=false&follow=false`, log as plain text
+/// - `GET /metrics`, prometheus metrics
+///
+/// other endpoints answer with the json response of control socket.
+pub struct HttpApi {
+    conf: Arc<Http>,
+    listener: Listener,
+}
+
+impl HttpApi {
+    pub async fn bind(conf: Http) -> Result<Self> {
+        let listener = match (&conf.socket, &conf.address) {
+            (Some(socket), _) => {
+                let path = Path::new(socket).to_path_buf();
+                if path.exists() && UnixStream::connect(&path).await.is_err() {
+                    fs::remove_file(&path).await?;
+                }
+                let listener = UnixListener::bind(&path)
+                    .context(format!("bind http socket path {:?} failed", path))?;
+                Listener::Unix(listener, path)
+            }
+            (None, Some(address)) => {
+                let listener = TcpListener::bind(address)
+                    .await
+                    .context(format!("bind http address {} failed", address))?;
+                let addr = listener.local_addr()?;
+                info!("serve http api on {}", addr);
+                if !addr.ip().is_loopback() {
+                    warn!("http api is exposed on non loopback address {}", addr);
+                }
+                Listener::Tcp(listener)
+            }
+            (None, None) => return Err(anyhow!("sup.http needs address or socket")),
+        };
+        if conf.token.is_none() && conf.username.is_none() {
+            warn!("http api is served without auth");
+        }
+        Ok(Self {
+            conf: Arc::new(conf),
+            listener,
+        })
+    }
+
+    /// address of tcp listener, none for unix socket
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Listener::Tcp(listener) => listener.local_addr().ok(),
+            Listener::Unix(..) => None,
+        }
+    }
+
+    /// serve connections until shutdown, unix socket is removed on return
+    pub async fn run(self, server: Arc<Server>, mut shutdown: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                biased;
+                _ = shutdown.changed() => break,
+                accepted = self.accept(&server) => {
+                    if let Err(e) = accepted {
+                        error!("accept http connection failed: {e}");
+                    }
+                }
+            }
+        }
+
+        if let Listener::Unix(_, path) = &self.listener {
+            if let Err(e) = fs::remove_file(path).await {
+                error!("remove http socket {:?} failed: {e}", path);
+            }
+        }
+        info!("http api exit");
+    }
+
+    async fn accept(&self, server: &Arc<Server>) -> Result<()> {
+        match &self.listener {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                info!("accept http connection from {}", addr);
+                self.serve(stream, server.clone());
+            }
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                info!("accept http connection from unix socket");
+                self.serve(stream, server.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// connections count against those of control socket, one over the cap
+    /// is closed right away
+    fn serve<S>(&self, stream: S, server: Arc<Server>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let permit = match server.admit() {
+            Some(permit) => Arc::new(permit),
+            None => {
+                warn!("close http connection, connections exceed {MAX_CONNECTIONS}");
+                return;
+            }
+        };
+        let conf = self.conf.clone();
+        let service =
+            service_fn(move |req| handle(server.clone(), conf.clone(), permit.clone(), req));
+        tokio::spawn(async move {
+            // idle connection is closed once no request arrives in time
+            if let Err(e) = HttpConn::new()
+                .http1_only(true)
+                .http1_header_read_timeout(READ_TIMEOUT)
+                .serve_connection(stream, service)
+                .await
+            {
+                error!("serve http connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    server: Arc<Server>,
+    conf: Arc<Http>,
+    permit: Arc<OwnedSemaphorePermit>,
+    req: HttpRequest<Body>,
+) -> Result<HttpResponse<Body>, Infallible> {
+    if !authorized(&conf, &req) {
+        let challenge = match conf.username {
+            Some(_) => "Basic realm=\"sup\"",
+            None => "Bearer",
+        };
+        return Ok(HttpResponse::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, challenge)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    if req.uri().path() == "/metrics" {
+        if req.method() != Method::GET {
+            return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+        }
+        return Ok(HttpResponse::builder()
+            .header(CONTENT_TYPE, metrics::CONTENT_TYPE)
+            .body(Body::from(metrics::render(server.controller())))
+            .unwrap());
+    }
+
+    let cmd = match req.uri().path() {
+        "/api/status" => Command::Status,
+        "/api/start" => Command::Start,
+        "/api/stop" => Command::Stop,
+        "/api/restart" => Command::Restart,
+        "/api/kill" => Command::Kill,
+        "/api/reload" => Command::Reload,
+        "/api/rotate" => Command::Rotate,
+        "/api/tail" => match tail_command(req.uri().query()) {
+            Ok(cmd) => cmd,
+            Err(message) => return Ok(error(ErrorKind::BadRequest, message)),
+        },
+        _ => return Ok(status(StatusCode::NOT_FOUND)),
+    };
+    let method = match cmd {
+        Command::Status | Command::Tail { .. } => Method::GET,
+        _ => Method::POST,
+    };
+    if req.method() != method {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    if let Command::Tail {
+        follow,
+        lines,
+        stderr,
+    } = cmd
+    {
+        return Ok(tail(&server, follow, lines, stderr, permit).await);
+    }
+    Ok(json(&server.handle_command(Request::new(cmd)).await))
+}
+
+fn authorized(conf: &Http, req: &HttpRequest<Body>) -> bool {
+    if conf.token.is_none() && conf.username.is_none() {
+        return true;
+    }
+    let header = match req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(header) => header,
+        None => return false,
+    };
+
+    if let (Some(token), Some(given)) = (&conf.token, header.strip_prefix("Bearer ")) {
+        return constant_time_eq(token.as_bytes(), given.as_bytes());
+    }
+    if let (Some(username), Some(password), Some(given)) = (
+        &conf.username,
+        &conf.password,
+        header.strip_prefix("Basic "),
+    ) {
+        let expected = format!("{}:{}", username, password);
+        return match STANDARD.decode(given) {
+            Ok(given) => constant_time_eq(expected.as_bytes(), &given),
+            Err(_) => false,
+        };
+    }
+    false
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn tail_command(query: Option<&str>) -> Result<Command, String> {
+    let (mut follow, mut lines, mut stderr) = (false, 10, false);
+    for pair in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+    {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, "true"));
+        match key {
+            "follow" => {
+                follow = value
+                    .parse()
+                    .map_err(|_| format!("invalid follow {value}"))?
+            }
+            "lines" => {
+                lines = value
+                    .parse()
+                    .map_err(|_| format!("invalid lines {value}"))?
+            }
+            "stderr" => {
+                stderr = value
+                    .parse()
+                    .map_err(|_| format!("invalid stderr {value}"))?
+            }
+            _ => return Err(format!("unknown parameter {key}")),
+        }
+    }
+    Ok(Command::Tail {
+        follow,
+        lines,
+        stderr,
+    })
+}
+
+/// log as plain text, in follow mode body is streamed until client is gone.
+/// streaming holds permit of the connection, so it counts until it ends
+async fn tail(
+    server: &Server,
+    follow: bool,
+    lines: usize,
+    stderr: bool,
+    permit: Arc<OwnedSemaphorePermit>,
+) -> HttpResponse<Body> {
+    let tail = match server.controller().log_path(stderr) {
+        Ok(path) => LogTail::open(&path, lines).await,
+        Err(e) => Err(e),
+    };
+    let mut tail = match tail {
+        Ok(tail) => tail,
+        Err(e) => return error((&e).into(), format!("tail failed: {e}")),
+    };
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let _permit = permit;
+        loop {
+            let data = match tail.read().await {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("read log failed: {e}");
+                    return;
+                }
+            };
+            if !data.is_empty() {
+                let sent = time::timeout(WRITE_TIMEOUT, sender.send_data(Bytes::from(data))).await;
+                if !matches!(sent, Ok(Ok(()))) {
+                    return;
+                }
+                continue;
+            }
+            if !follow {
+                return;
+            }
+            // body is dropped once client is gone, sender only notices it
+            // when polled
+            let closed = future::poll_fn(|cx| {
+                Poll::Ready(matches!(sender.poll_ready(cx), Poll::Ready(Err(_))))
+            });
+            if closed.await {
+                return;
+            }
+            time::sleep(TAIL_POLL_INTERVAL).await;
+        }
+    });
+    HttpResponse::builder()
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body)
+        .unwrap()
+}
+
+fn json(resp: &Response) -> HttpResponse<Body> {
+    let code = match resp.body() {
+        ResponseBody::Error { kind, .. } => match kind {
+            ErrorKind::Busy | ErrorKind::NotRunning => StatusCode::CONFLICT,
+            ErrorKind::InvalidArgument | ErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::OK,
+    };
+    match serde_json::to_vec(resp) {
+        Ok(body) => HttpResponse::builder()
+            .status(code)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => {
+            error!("encode response failed: {e}");
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn error(kind: ErrorKind, message: String) -> HttpResponse<Body> {
+    json(&Response::new(
+        ResponseBody::Error { kind, message },
+        Some(std::process::id()),
+    ))
+}
+
+fn status(code: StatusCode) -> HttpResponse<Body> {
+    HttpResponse::builder()
+        .status(code)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -0,0 +1,268 @@
+use std::{collections::VecDeque, process::Stdio, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, Command},
+    sync::{broadcast, watch},
+    time::{self, Instant},
+};
+
+use crate::config::config::Listener;
+
+use super::event::Event;
+
+const RESTART_INTERVAL: Duration = Duration::from_secs(1);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+// pending events are still delivered in this time after sup exits
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// program run by sup which receives events on stdin, one event at a time:
+///
+/// 1. listener writes `READY` line to stdout once it can handle events
+/// 2. sup writes an event as a json line to stdin of listener
+/// 3. listener writes `OK` line once the event is handled, or `FAIL` line to
+///    have the event sent again later, either line means ready for next event
+///
+/// event being handled when listener exits is sent again to the restarted
+/// listener. stderr of listener is inherited from sup.
+pub struct EventListener {
+    conf: Listener,
+    events: broadcast::Receiver<Event>,
+    pending: VecDeque<Event>,
+    // front of pending is sent and not acknowledged yet
+    in_flight: bool,
+    // set once shutdown is signaled
+    deadline: Option<Instant>,
+}
+
+enum Served {
+    Exited,
+    Done,
+}
+
+impl EventListener {
+    pub fn new(conf: Listener, events: broadcast::Receiver<Event>) -> Self {
+        Self {
+            conf,
+            events,
+            pending: VecDeque::new(),
+            in_flight: false,
+            deadline: None,
+        }
+    }
+
+    /// run listener and restart it whenever it exits until shutdown
+    pub async fn run(mut self, mut shutdown: watch::Receiver<bool>) {
+        loop {
+            let served = match self.spawn() {
+                Ok(mut child) => {
+                    let served = self.serve(&mut child, &mut shutdown).await;
+                    if let Err(e) = child.kill().await {
+                        error!("kill listener {} failed: {e}", self.conf.name);
+                    }
+                    served
+                }
+                Err(e) => {
+                    error!("spawn listener {} failed: {e}", self.conf.name);
+                    Served::Exited
+                }
+            };
+            if let Served::Done = served {
+                info!("listener {} exit", self.conf.name);
+                return;
+            }
+            if self.deadline.is_some() {
+                return;
+            }
+
+            warn!("listener {} exited, restarting", self.conf.name);
+            tokio::select! {
+                _ = time::sleep(RESTART_INTERVAL) => {}
+                _ = shutdown.changed() => return,
+            }
+        }
+    }
+
+    fn spawn(&self) -> Result<Child> {
+        let child = Command::new(&self.conf.path)
+            .args(self.conf.args.clone().unwrap_or_default())
+            .envs(self.conf.envs.clone().unwrap_or_default())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("spawn {} failed", self.conf.path))?;
+        info!("listener {} started", self.conf.name);
+        Ok(child)
+    }
+
+    async fn serve(&mut self, child: &mut Child, shutdown: &mut watch::Receiver<bool>) -> Served {
+        let (mut stdin, mut lines) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, BufReader::new(stdout).lines()),
+            _ => return Served::Exited,
+        };
+        let mut ready = false;
+        self.in_flight = false;
+        loop {
+            if ready && !self.in_flight {
+                match self.pending.front() {
+                    Some(event) => {
+                        if let Err(e) = Self::send(&mut stdin, event).await {
+                            warn!("send event to listener {} failed: {e}", self.conf.name);
+                            return Served::Exited;
+                        }
+                        self.in_flight = true;
+                    }
+                    None if self.deadline.is_some() => return Served::Done,
+                    None => {}
+                }
+            }
+
+            let deadline = self.deadline.unwrap_or_else(Instant::now);
+            tokio::select! {
+                _ = shutdown.changed(), if self.deadline.is_none() => {
+                    self.deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+                    while let Ok(event) = self.events.try_recv() {
+                        self.push(event);
+                    }
+                    if self.pending.is_empty() {
+                        return Served::Done;
+                    }
+                }
+                _ = time::sleep_until(deadline), if self.deadline.is_some() => {
+                    warn!(
+                        "listener {} exits with {} events undelivered",
+                        self.conf.name,
+                        self.pending.len()
+                    );
+                    return Served::Done;
+                }
+                event = self.events.recv(), if self.deadline.is_none() => match event {
+                    Ok(event) => self.push(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("listener {} lagged, {n} events dropped", self.conf.name);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        self.deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+                    }
+                },
+                line = lines.next_line() => match self.handle_line(line).await {
+                    Ok(()) => ready = true,
+                    Err(e) => {
+                        warn!("listener {}: {e}", self.conf.name);
+                        return Served::Exited;
+                    }
+                },
+            }
+        }
+    }
+
+    /// handle line written by listener, error if listener exited or broke
+    /// the protocol
+    async fn handle_line(&mut self, line: std::io::Result<Option<String>>) -> Result<()> {
+        let line = line
+            .context("read stdout failed")?
+            .ok_or_else(|| anyhow!("stdout closed"))?;
+        match line.trim_end() {
+            // event in flight is still acknowledged later
+            "READY" => {}
+            "OK" | "FAIL" if !self.in_flight => {
+                return Err(anyhow!("{:?} without event", line.trim_end()))
+            }
+            "OK" => {
+                self.pending.pop_front();
+                self.in_flight = false;
+            }
+            "FAIL" => {
+                warn!(
+                    "listener {} failed to handle event, retry in {:?}",
+                    self.conf.name, RETRY_INTERVAL
+                );
+                time::sleep(RETRY_INTERVAL).await;
+                self.in_flight = false;
+            }
+            line => return Err(anyhow!("unexpected line {:?}", line)),
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, event: Event) {
+        if !self.conf.events.is_empty() && !self.conf.events.contains(&event.kind.event_name()) {
+            return;
+        }
+        if self.pending.len() >= MAX_PENDING_EVENTS {
+            warn!(
+                "listener {} has {} pending events, oldest is dropped",
+                self.conf.name, MAX_PENDING_EVENTS
+            );
+            // event in flight is kept for its acknowledgement
+            let oldest = if self.in_flight { 1 } else { 0 };
+            self.pending.remove(oldest);
+        }
+        self.pending.push_back(event);
+    }
+
+    async fn send(stdin: &mut ChildStdin, event: &Event) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        stdin.write_all(&line).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::config::EventName,
+        controller::{controller::ExitReason, event::EventKind},
+    };
+
+    #[tokio::test]
+    async fn async_listener_test() {
+        let dir = std::env::temp_dir().join(format!("sup-listener-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("events");
+        let conf = Listener {
+            name: "record".to_string(),
+            path: "/bin/sh".to_string(),
+            args: Some(vec![
+                "-c".to_string(),
+                format!(
+                    "echo READY; while read l; do echo \"$l\" >> {}; echo OK; done",
+                    out.display()
+                ),
+            ]),
+            envs: None,
+            events: vec![EventName::Exited],
+        };
+
+        let (events, recv) = broadcast::channel(16);
+        let (shutdown, shutdown_recv) = watch::channel(false);
+        let run = tokio::spawn(EventListener::new(conf, recv).run(shutdown_recv));
+        let exited = Event::new(
+            "run",
+            EventKind::Exited {
+                exit: ExitReason::Code(1),
+            },
+        );
+        events
+            .send(Event::new("run", EventKind::Started { pid: 42 }))
+            .unwrap();
+        events.send(exited.clone()).unwrap();
+        // pending events are delivered before listener exits
+        shutdown.send_replace(true);
+        run.await.unwrap();
+
+        let lines = std::fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(serde_json::from_str::<Event>(lines[0]).unwrap(), exited);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::rotater::rotater::LogStats;
+
+use super::controller::{ExitReason, ProcessController, ResourceUsage};
+
+/// prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// counter of log stats
+type Counter = fn(&LogStats) -> &AtomicU64;
+type Usage = fn(&ResourceUsage) -> f64;
+
+/// metrics of program and its logs in prometheus text format
+pub fn render(pc: &ProcessController) -> String {
+    let st = pc.status();
+    let program = [("program", st.name.as_str())];
+    let mut w = MetricsWriter::default();
+
+    w.family("sup_program_up", "1 if program is running", "gauge");
+    w.sample("sup_program_up", &program, st.pid.is_some() as u64 as f64);
+    w.family(
+        "sup_program_restarts_total",
+        "restarts of program by sup",
+        "counter",
+    );
+    w.sample("sup_program_restarts_total", &program, st.restarts as f64);
+    w.family(
+        "sup_program_last_exit_code",
+        "exit code of last exit, absent if killed by signal",
+        "gauge",
+    );
+    w.family(
+        "sup_program_last_exit_signal",
+        "signal killed program at last exit",
+        "gauge",
+    );
+    match st.last_exit {
+        Some(ExitReason::Code(c)) => w.sample("sup_program_last_exit_code", &program, c as f64),
+        Some(ExitReason::Signal(s)) => w.sample("sup_program_last_exit_signal", &program, s as f64),
+        None => {}
+    }
+    w.family(
+        "sup_program_uptime_seconds",
+        "seconds since program is spawned",
+        "gauge",
+    );
+    if let Some(uptime) = st.uptime {
+        w.sample("sup_program_uptime_seconds", &program, uptime as f64);
+    }
+
+    // usage of program and its descendants, absent until sampled
+    let usage: [(&str, &str, &str, Usage); 7] = [
+        (
+            "sup_program_cpu_seconds_total",
+            "user and system cpu time",
+            "counter",
+            |u| u.cpu_seconds,
+        ),
+        (
+            "sup_program_cpu_percent",
+            "cpu time in last sample interval, 100 for a full core",
+            "gauge",
+            |u| u.cpu_percent,
+        ),
+        (
+            "sup_program_resident_memory_bytes",
+            "resident memory",
+            "gauge",
+            |u| u.rss_bytes as f64,
+        ),
+        (
+            "sup_program_open_fds",
+            "open file descriptors",
+            "gauge",
+            |u| u.fds as f64,
+        ),
+        ("sup_program_threads", "threads", "gauge", |u| {
+            u.threads as f64
+        }),
+        (
+            "sup_program_read_bytes_total",
+            "bytes read from storage",
+            "counter",
+            |u| u.read_bytes as f64,
+        ),
+        (
+            "sup_program_write_bytes_total",
+            "bytes written to storage",
+            "counter",
+            |u| u.write_bytes as f64,
+        ),
+    ];
+    for (name, help, kind, value) in usage {
+        w.family(name, help, kind);
+        if let Some(u) = &st.usage {
+            w.sample(name, &program, value(u));
+        }
+    }
+
+    let logs = pc.log_stats();
+    let counters: [(&str, &str, Counter); 4] = [
+        ("sup_log_written_bytes_total", "bytes written to log", |s| {
+            &s.written_bytes
+        }),
+        ("sup_log_rotations_total", "rotations of log", |s| {
+            &s.rotations
+        }),
+        (
+            "sup_log_compress_saved_bytes_total",
+            "size of rotated logs minus size of their gzip",
+            |s| &s.compress_saved_bytes,
+        ),
+        (
+            "sup_log_backups_deleted_total",
+            "backups deleted for max days or max backups",
+            |s| &s.backups_deleted,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        w.family(name, help, "counter");
+        for (path, stats) in &logs {
+            let value = counter(stats);
+            let labels = [("program", st.name.as_str()), ("log", path.as_str())];
+            w.sample(name, &labels, value.load(Ordering::Relaxed) as f64);
+        }
+    }
+    w.family(
+        "sup_log_compress_seconds",
+        "time spent compressing rotated logs",
+        "summary",
+    );
+    for (path, stats) in &logs {
+        let labels = [("program", st.name.as_str()), ("log", path.as_str())];
+        let micros = stats.compress_micros.load(Ordering::Relaxed);
+        w.sample("sup_log_compress_seconds_sum", &labels, micros as f64 / 1e6);
+        w.sample(
+            "sup_log_compress_seconds_count",
+            &labels,
+            stats.compressions.load(Ordering::Relaxed) as f64,
+        );
+    }
+
+    w.out
+}
+
+#[derive(Default)]
+struct MetricsWriter {
+    out: String,
+}
+
+impl MetricsWriter {
+    fn family(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = writeln!(self.out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_writer_test() {
+        let mut w = MetricsWriter::default();
+        w.family("sup_program_up", "1 if program is running", "gauge");
+        w.sample("sup_program_up", &[("program", "a\"b")], 1.0);
+        w.sample(
+            "sup_log_compress_seconds_sum",
+            &[("log", "/tmp/run.log")],
+            0.25,
+        );
+        assert_eq!(
+            w.out,
+            "# HELP sup_program_up 1 if program is running\n\
+             # TYPE sup_program_up gauge\n\
+             sup_program_up{program=\"a\\\"b\"} 1\n\
+             sup_log_compress_seconds_sum{log=\"/tmp/run.log\"} 0.25\n"
+        );
+    }
+}
@@ -1,4 +1,24 @@
+mod activation;
+mod cgroup;
 pub mod client;
 pub mod command;
+mod confinement;
+#[allow(clippy::module_inception)]
 pub mod controller;
+mod credential;
+pub mod error;
+pub mod event;
+mod http;
+mod listener;
+mod metrics;
+mod notify;
+mod output;
+mod probe;
+mod procfs;
+pub mod protocol;
+mod readiness;
+mod rlimit;
+mod sandbox;
 pub mod server;
+mod sys;
+mod tail;
@@ -0,0 +1,239 @@
+use std::{
+    ffi::{CString, OsString},
+    io,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{chown, PermissionsExt},
+        io::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+use tokio::{io::Interest, net::UnixDatagram, sync::broadcast, task::JoinHandle};
+
+use super::{procfs, sys::check};
+
+const NOTIFY_CHANNEL_LENGTH: usize = 64;
+const MAX_DATAGRAM_SIZE: usize = 4096;
+// room for credentials and a few fds sent along, which are closed
+const CONTROL_SIZE: usize = 64;
+
+/// assignment such as `READY=1` and pid of the process which sent it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub pid: u32,
+    pub message: String,
+}
+
+impl Notification {
+    /// sent by program running as pid or one of its descendants, anyone
+    /// else able to reach the socket is ignored
+    pub fn sent_by(&self, pid: u32) -> bool {
+        procfs::in_tree(self.pid, pid)
+    }
+}
+
+/// receives sd_notify style datagrams from program, such as `READY=1`, path
+/// of the socket is passed to program by `NOTIFY_SOCKET`
+pub struct NotifySocket {
+    // private dir of socket
+    dir: PathBuf,
+    path: PathBuf,
+    messages: broadcast::Sender<Notification>,
+    reader: JoinHandle<()>,
+}
+
+impl NotifySocket {
+    /// bind socket in a new private dir, both owned by owner who runs
+    /// program, and removed on drop
+    pub fn bind(name: &str, owner: (Option<u32>, Option<u32>)) -> Result<Self> {
+        let dir = make_private_dir(&std::env::temp_dir().join(format!("sup-{}-", name)))
+            .context("create notify socket dir failed")?;
+        match Self::bind_in(dir.clone(), owner) {
+            Ok(socket) => Ok(socket),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    fn bind_in(dir: PathBuf, owner: (Option<u32>, Option<u32>)) -> Result<Self> {
+        let path = dir.join("notify");
+        let socket =
+            UnixDatagram::bind(&path).context(format!("bind notify socket {:?} failed", path))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        if owner != (None, None) {
+            chown(&dir, owner.0, owner.1).context(format!("chown {:?} failed", dir))?;
+            chown(&path, owner.0, owner.1).context(format!("chown {:?} failed", path))?;
+        }
+        // kernel attaches pid of sender to every datagram
+        let on: libc::c_int = 1;
+        check(unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                &on as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })?;
+
+        let (messages, _) = broadcast::channel(NOTIFY_CHANNEL_LENGTH);
+        let reader = tokio::spawn(Self::read(socket, messages.clone()));
+        Ok(Self {
+            dir,
+            path,
+            messages,
+            reader,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// assignments sent from now on, one per message
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.messages.subscribe()
+    }
+
+    async fn read(socket: UnixDatagram, messages: broadcast::Sender<Notification>) {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        loop {
+            let received = match socket.readable().await {
+                Ok(()) => socket.try_io(Interest::READABLE, || {
+                    recv_with_pid(socket.as_raw_fd(), &mut buf)
+                }),
+                Err(e) => Err(e),
+            };
+            let (n, pid) = match received {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => {
+                    error!("read notify socket failed: {e}");
+                    return;
+                }
+            };
+            let pid = match pid {
+                Some(pid) => pid,
+                None => {
+                    warn!("notify message without credentials is ignored");
+                    continue;
+                }
+            };
+            let datagram = match std::str::from_utf8(&buf[..n]) {
+                Ok(datagram) => datagram,
+                Err(_) => {
+                    warn!("notify message of {} is not utf8", pid);
+                    continue;
+                }
+            };
+            for line in datagram.lines().filter(|l| !l.is_empty()) {
+                // no subscriber is not an error
+                let _ = messages.send(Notification {
+                    pid,
+                    message: line.to_string(),
+                });
+            }
+        }
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            error!("remove notify socket {:?} failed: {e}", self.path);
+        }
+    }
+}
+
+/// dir named prefix with random suffix, created with mode 0700 so nobody
+/// else can place or replace a socket in it
+fn make_private_dir(prefix: &Path) -> io::Result<PathBuf> {
+    let mut template = prefix.as_os_str().as_bytes().to_vec();
+    template.extend_from_slice(b"XXXXXX");
+    let template = CString::new(template)?;
+    let mut template = template.into_bytes_with_nul();
+    if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+/// receive a datagram and pid of its sender from SCM_CREDENTIALS, fds sent
+/// along are closed
+fn recv_with_pid(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, Option<u32>)> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64 keeps control messages aligned
+    let mut control = [0u64; CONTROL_SIZE / 8];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = CONTROL_SIZE as _;
+    let n = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut pid = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) => {
+                    let cred = std::ptr::read_unaligned(data as *const libc::ucred);
+                    pid = Some(cred.pid as u32);
+                }
+                (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                    let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                    for i in 0..len / std::mem::size_of::<libc::c_int>() {
+                        let fd = std::ptr::read_unaligned((data as *const libc::c_int).add(i));
+                        libc::close(fd);
+                    }
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((n as usize, pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_notify_test() {
+        let socket = NotifySocket::bind("notify-test", (None, None)).unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(socket.path()), 0o600);
+        assert_eq!(mode(socket.path().parent().unwrap()), 0o700);
+
+        let mut messages = socket.subscribe();
+        let client = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        client
+            .send_to(b"READY=1\nSTATUS=serving\n", socket.path())
+            .unwrap();
+        let ready = messages.recv().await.unwrap();
+        assert_eq!(ready.message, "READY=1");
+        assert_eq!(ready.pid, std::process::id());
+        assert!(ready.sent_by(std::process::id()));
+        assert!(!ready.sent_by(1));
+        assert_eq!(messages.recv().await.unwrap().message, "STATUS=serving");
+
+        let dir = socket.path().parent().unwrap().to_path_buf();
+        drop(socket);
+        assert!(!dir.exists());
+    }
+}
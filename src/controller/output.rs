@@ -0,0 +1,128 @@
+use std::{
+    io::ErrorKind,
+    os::unix::fs::{chown, MetadataExt},
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, Mutex},
+};
+
+use crate::{
+    config::config::Log,
+    rotater::rotater::{LogStats, RotateHandle},
+};
+
+use super::tail::Utf8Decoder;
+
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// uid and gid of log files, owner is unchanged if none
+pub type Owner = (Option<u32>, Option<u32>);
+
+/// LogWriter appends program output to log path, a rotate task is sent
+/// once the file grows beyond max size. The rotater renames the file away,
+/// writer notices the path points to another inode and reopens it.
+pub struct LogWriter {
+    conf: Log,
+    rotate: RotateHandle,
+    stats: Arc<LogStats>,
+    owner: Owner,
+    file: File,
+    ino: u64,
+    size: u64,
+}
+
+impl LogWriter {
+    pub async fn open(conf: Log, rotate: RotateHandle, owner: Owner) -> Result<Self> {
+        let (file, ino, size) = Self::open_file(&conf.path, owner).await?;
+        Ok(Self {
+            stats: rotate.stats(&conf.path),
+            conf,
+            rotate,
+            owner,
+            file,
+            ino,
+            size,
+        })
+    }
+
+    async fn open_file(path: &str, owner: Owner) -> Result<(File, u64, u64)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .context(format!("open log file {} failed", path))?;
+        let meta = file.metadata().await?;
+        if owner != (None, None) {
+            chown(path, owner.0, owner.1).context(format!("chown log file {} failed", path))?;
+        }
+        Ok((file, meta.ino(), meta.len()))
+    }
+
+    async fn reopen_if_rotated(&mut self) -> Result<()> {
+        let rotated = match tokio::fs::metadata(&self.conf.path).await {
+            Ok(meta) => meta.ino() != self.ino,
+            Err(e) if e.kind() == ErrorKind::NotFound => true,
+            Err(e) => return Err(e.into()),
+        };
+        if rotated {
+            self.file.flush().await?;
+            (self.file, self.ino, self.size) = Self::open_file(&self.conf.path, self.owner).await?;
+            info!("reopen rotated log {}", self.conf.path);
+        }
+        Ok(())
+    }
+
+    pub async fn write(&mut self, buf: &[u8]) -> Result<()> {
+        self.reopen_if_rotated().await?;
+        self.file.write_all(buf).await?;
+        self.size += buf.len() as u64;
+        self.stats
+            .written_bytes
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        if self.size >= self.conf.max_size {
+            self.rotate.add_rotate_task(self.conf.clone()).await;
+            // new size is read after reopen
+            self.size = 0;
+        }
+        Ok(())
+    }
+
+    /// copy output of program to writer until eof, output is also sent to
+    /// outputs such as attached terminals in whole characters, as stdout
+    /// and stderr share them
+    pub async fn copy<R: AsyncRead + Unpin>(
+        mut reader: R,
+        writer: Arc<Mutex<Self>>,
+        outputs: Vec<broadcast::Sender<String>>,
+    ) {
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        let mut decoder = Utf8Decoder::default();
+        loop {
+            let n = match reader.read(&mut buf).await {
+                Ok(0) => return,
+                Ok(n) => n,
+                Err(e) => {
+                    error!("read program output failed: {e}");
+                    return;
+                }
+            };
+            if let Err(e) = writer.lock().await.write(&buf[..n]).await {
+                error!("write program output failed: {e}");
+            }
+            let data = decoder.decode(&buf[..n]);
+            for output in outputs.iter().filter(|o| o.receiver_count() > 0) {
+                if !data.is_empty() {
+                    // terminal may detach at any time
+                    let _ = output.send(data.clone());
+                }
+            }
+        }
+    }
+}
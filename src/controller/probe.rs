@@ -0,0 +1,232 @@
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+    time,
+};
+
+use crate::config::config::Probe;
+
+use super::confinement::Confinement;
+
+// status line and headers are enough to judge the response
+const HTTP_RESPONSE_HEAD_SIZE: usize = 1024;
+
+/// run probe once, error tells why it failed. exec probe runs in the
+/// namespaces and as the user of program, confinement is only built for it
+pub async fn check(
+    probe: &Probe,
+    confinement: impl FnOnce() -> Result<Confinement>,
+    timeout: Duration,
+) -> Result<()> {
+    match time::timeout(timeout, run(probe, confinement)).await {
+        Ok(res) => res,
+        Err(_) => Err(anyhow!("timed out after {:?}", timeout)),
+    }
+}
+
+async fn run(probe: &Probe, confinement: impl FnOnce() -> Result<Confinement>) -> Result<()> {
+    match probe {
+        Probe::Exec { command } => exec(command, confinement()?).await,
+        Probe::Tcp { address } => {
+            TcpStream::connect(address)
+                .await
+                .context(format!("connect {} failed", address))?;
+            Ok(())
+        }
+        Probe::Http { url } => http(url).await,
+    }
+}
+
+async fn exec(command: &[String], confinement: Confinement) -> Result<()> {
+    let (path, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("empty command"))?;
+    let mut cmd = Command::new(path);
+    cmd.envs(confinement.credential().envs());
+    confinement.confine(&mut cmd, None);
+    let status = cmd
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // probe timed out is dropped
+        .kill_on_drop(true)
+        .status()
+        .await
+        .context(format!("run {} failed", path))?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", path, status));
+    }
+    Ok(())
+}
+
+/// GET url over plain http, 2xx and 3xx are healthy
+async fn http(url: &str) -> Result<()> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("only http url is supported: {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .context(format!("connect {} failed", address))?;
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: sup\r\nConnection: close\r\n\r\n",
+                path, host
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut head = vec![0; HTTP_RESPONSE_HEAD_SIZE];
+    let mut n = 0;
+    while n < head.len() && !head[..n].contains(&b'\n') {
+        match stream.read(&mut head[n..]).await? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    let line = String::from_utf8_lossy(&head[..n]);
+    let status = line
+        .lines()
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("invalid response from {}", url))?;
+    if !(200..400).contains(&status) {
+        return Err(anyhow!("{} answered with {}", url, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn stub(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let resp = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    fn unconfined() -> Result<Confinement> {
+        Confinement::new(&toml::from_str("path = \"/bin/true\"").unwrap(), None)
+    }
+
+    #[tokio::test]
+    async fn async_probe_test() {
+        let timeout = Duration::from_secs(1);
+        let exec = |c: &[&str]| Probe::Exec {
+            command: c.iter().map(|s| s.to_string()).collect(),
+        };
+        assert!(check(&exec(&["/bin/true"]), unconfined, timeout)
+            .await
+            .is_ok());
+        assert!(check(&exec(&["/bin/false"]), unconfined, timeout)
+            .await
+            .is_err());
+        let e = check(&exec(&["/bin/sleep", "5"]), unconfined, timeout)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().starts_with("timed out"));
+        // exec probe runs as user of program
+        if unsafe { libc::geteuid() } == 0 {
+            let nobody = || {
+                let conf = toml::from_str("path = \"/bin/true\"\nuser = \"nobody\"").unwrap();
+                Confinement::new(&conf, None)
+            };
+            let probe = exec(&["/bin/sh", "-c", "test $(id -u) != 0"]);
+            assert!(check(&probe, nobody, timeout).await.is_ok());
+        }
+
+        let ok = stub("200 OK").await;
+        let failing = stub("503 Service Unavailable").await;
+        assert!(check(
+            &Probe::Tcp {
+                address: ok.clone()
+            },
+            unconfined,
+            timeout
+        )
+        .await
+        .is_ok());
+        let http = |addr: &str| Probe::Http {
+            url: format!("http://{}/health", addr),
+        };
+        assert!(check(&http(&ok), unconfined, timeout).await.is_ok());
+        let e = check(&http(&failing), unconfined, timeout)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().ends_with("answered with 503"));
+
+        // port of a dropped listener is closed
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        assert!(check(&Probe::Tcp { address: closed }, unconfined, timeout)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn async_exec_probe_private_network_test() {
+        let conf =
+            toml::from_str("path = \"/bin/sleep\"\n[sandbox]\nprivateNetwork = true").unwrap();
+        let mut cmd = Command::new("/bin/sleep");
+        cmd.arg("30").kill_on_drop(true);
+        Confinement::new(&conf, None)
+            .unwrap()
+            .confine(&mut cmd, None);
+        let program = match cmd.spawn() {
+            Ok(program) => program,
+            // containers may refuse namespaces
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EPERM | libc::EINVAL)) => return,
+            Err(e) => panic!("spawn failed: {e}"),
+        };
+        let pid = program.id().unwrap();
+
+        let probe = Probe::Exec {
+            command: vec![
+                "/bin/sh".to_string(),
+                "-c".to_string(),
+                format!(
+                    "test \"$(readlink /proc/self/ns/net)\" = \"$(readlink /proc/{}/ns/net)\"",
+                    pid
+                ),
+            ],
+        };
+        let timeout = Duration::from_secs(1);
+        assert!(check(&probe, || Confinement::probe(&conf, pid), timeout)
+            .await
+            .is_ok());
+        // a new namespace of its own can't reach program
+        assert!(check(&probe, || Confinement::new(&conf, None), timeout)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+
+/// resource usage of processes read from /proc
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ProcSample {
+    // user and system cpu time
+    pub cpu_seconds: f64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub fds: u64,
+    // bytes fetched from and sent to storage
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+impl ProcSample {
+    fn add(&mut self, other: &Self) {
+        self.cpu_seconds += other.cpu_seconds;
+        self.rss_bytes += other.rss_bytes;
+        self.threads += other.threads;
+        self.fds += other.fds;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+}
+
+/// usage of process and all its descendants, descendants exiting while
+/// sampled are skipped
+pub fn sample_tree(pid: u32) -> Result<ProcSample> {
+    let mut total = sample(pid)?;
+    for child in descendants(pid) {
+        if let Ok(s) = sample(child) {
+            total.add(&s);
+        }
+    }
+    Ok(total)
+}
+
+fn sample(pid: u32) -> Result<ProcSample> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .context(format!("read stat of {} failed", pid))?;
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid))
+        .context(format!("read status of {} failed", pid))?;
+    let mut sample = ProcSample {
+        cpu_seconds: parse_stat_cpu(&stat, clock_ticks())?,
+        ..Default::default()
+    };
+    parse_status(&status, &mut sample);
+
+    // fd and io of processes of other users are not readable
+    if let Ok(fds) = std::fs::read_dir(format!("/proc/{}/fd", pid)) {
+        sample.fds = fds.count() as u64;
+    }
+    if let Ok(io) = std::fs::read_to_string(format!("/proc/{}/io", pid)) {
+        parse_io(&io, &mut sample);
+    }
+    Ok(sample)
+}
+
+fn descendants(pid: u32) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    for entry in entries.flatten() {
+        let child = match entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(child) => child,
+            None => continue,
+        };
+        let ppid = std::fs::read_to_string(format!("/proc/{}/stat", child))
+            .ok()
+            .and_then(|stat| stat_fields(&stat).get(1).and_then(|f| f.parse().ok()));
+        if let Some(ppid) = ppid {
+            children.entry(ppid).or_default().push(child);
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut queue = vec![pid];
+    while let Some(p) = queue.pop() {
+        for &child in children.get(&p).map(Vec::as_slice).unwrap_or_default() {
+            found.push(child);
+            queue.push(child);
+        }
+    }
+    found
+}
+
+/// pid is program at root or one of its descendants, such as a child that
+/// daemonized and kept the process group of program. pid that already
+/// exited is not
+pub fn in_tree(pid: u32, root: u32) -> bool {
+    let mut p = pid;
+    // pid 1 adopts orphans, it is no descendant
+    while p > 1 {
+        if p == root {
+            return true;
+        }
+        let stat = match std::fs::read_to_string(format!("/proc/{}/stat", p)) {
+            Ok(stat) => stat,
+            Err(_) => return false,
+        };
+        let fields = stat_fields(&stat);
+        if fields.get(2).and_then(|f| f.parse().ok()) == Some(root) {
+            return true;
+        }
+        p = match fields.get(1).and_then(|f| f.parse().ok()) {
+            Some(ppid) => ppid,
+            None => return false,
+        };
+    }
+    false
+}
+
+/// some process of group is still running, zombies left for a parent or
+/// an init that doesn't reap them are not
+pub fn group_alive(pgid: u32) -> bool {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().any(|entry| {
+        if entry
+            .file_name()
+            .to_str()
+            .is_none_or(|n| n.parse::<u32>().is_err())
+        {
+            return false;
+        }
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => return false,
+        };
+        let fields = stat_fields(&stat);
+        fields.first() != Some(&"Z") && fields.get(2).and_then(|f| f.parse().ok()) == Some(pgid)
+    })
+}
+
+/// fields after comm, which may contain spaces, start from state
+fn stat_fields(stat: &str) -> Vec<&str> {
+    stat.rsplit_once(')')
+        .map(|(_, rest)| rest.split_whitespace().collect())
+        .unwrap_or_default()
+}
+
+fn parse_stat_cpu(stat: &str, clock_ticks: u64) -> Result<f64> {
+    let fields = stat_fields(stat);
+    let field = |i: usize| -> Result<u64> {
+        fields
+            .get(i)
+            .ok_or_else(|| anyhow!("stat has {} fields", fields.len()))?
+            .parse()
+            .context(format!("parse stat field {} failed", i))
+    };
+    Ok((field(11)? + field(12)?) as f64 / clock_ticks as f64)
+}
+
+fn parse_status(status: &str, sample: &mut ProcSample) {
+    for line in status.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let value = value.split_whitespace().next().unwrap_or_default();
+        match key {
+            "VmRSS" => sample.rss_bytes = value.parse::<u64>().unwrap_or_default() * 1024,
+            "Threads" => sample.threads = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+}
+
+fn parse_io(io: &str, sample: &mut ProcSample) {
+    for line in io.lines() {
+        match line.split_once(": ") {
+            Some(("read_bytes", v)) => sample.read_bytes = v.parse().unwrap_or_default(),
+            Some(("write_bytes", v)) => sample.write_bytes = v.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+}
+
+fn clock_ticks() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        n if n > 0 => n as u64,
+        _ => 100,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_test() {
+        let stat = "42 (my prog) S 1 42 42 0 -1 4194560 300 0 0 0 150 50 0 0 20 0 1 0 \
+                    1000 12345678 256 18446744073709551615";
+        assert_eq!(parse_stat_cpu(stat, 100).unwrap(), 2.0);
+        assert_eq!(stat_fields(stat)[1], "1");
+        assert!(parse_stat_cpu("42 (sleep) S 1", 100).is_err());
+
+        let mut sample = ProcSample::default();
+        parse_status(
+            "Name:\tsleep\nVmRSS:\t    1024 kB\nThreads:\t3\n",
+            &mut sample,
+        );
+        parse_io(
+            "rchar: 10\nread_bytes: 4096\nwrite_bytes: 8192\n",
+            &mut sample,
+        );
+        assert_eq!(
+            sample,
+            ProcSample {
+                rss_bytes: 1024 * 1024,
+                threads: 3,
+                read_bytes: 4096,
+                write_bytes: 8192,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn sample_tree_test() {
+        let mut child = std::process::Command::new("/bin/sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let pid = std::process::id();
+        assert!(descendants(pid).contains(&child.id()));
+
+        let own = sample(pid).unwrap();
+        assert!(own.rss_bytes > 0 && own.fds > 0 && own.threads > 0);
+        let sleep = sample_tree(child.id()).unwrap();
+        assert_eq!(sleep.threads, 1);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::error::ErrorKind;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+// oldest client version server still talks to
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+const BYTES_PER_LENGTH: usize = 4;
+const MAX_FRAME_LENGTH: usize = 16 << 20;
+
+/// first message of a connection, server replies HelloAck and then answers
+/// any number of requests in order until the client closes the socket
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Hello {
+    pub version: u32,
+}
+
+impl Hello {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version)
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum HelloAck {
+    Accepted { version: u32, sup_pid: u32 },
+    Rejected { kind: ErrorKind, message: String },
+}
+
+/// every message on the control socket is a frame: 4 bytes big endian
+/// length followed by json payload
+pub async fn write_frame<W: AsyncWrite + Unpin, T: serde::Serialize>(
+    w: &mut W,
+    msg: &T,
+) -> Result<()> {
+    let payload = serde_json::to_vec(msg).context("encode frame failed")?;
+    if payload.len() > MAX_FRAME_LENGTH {
+        return Err(anyhow!(
+            "frame length {} exceeds {}",
+            payload.len(),
+            MAX_FRAME_LENGTH
+        ));
+    }
+
+    let mut frame = Vec::with_capacity(BYTES_PER_LENGTH + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    w.write_all(&frame).await.context("write frame failed")?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// read payload of next frame, none if peer closed between frames
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut len = [0; BYTES_PER_LENGTH];
+    match r.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("read frame length failed"),
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LENGTH {
+        return Err(anyhow!("frame length {} exceeds {}", len, MAX_FRAME_LENGTH));
+    }
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)
+        .await
+        .context("read frame payload failed")?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_frame_test() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        write_frame(&mut client, &Hello::new()).await.unwrap();
+        drop(client);
+
+        let payload = read_frame(&mut server).await.unwrap().unwrap();
+        assert_eq!(payload, br#"{"version":1}"#);
+        let hello: Hello = serde_json::from_slice(&payload).unwrap();
+        assert!(hello.is_compatible());
+        assert!(read_frame(&mut server).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn async_short_frame_test() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0, 0, 0, 8, b'{']).await.unwrap();
+        drop(client);
+
+        assert!(read_frame(&mut server).await.is_err());
+    }
+}
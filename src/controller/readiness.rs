@@ -0,0 +1,156 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Result};
+use log::debug;
+use regex::Regex;
+use tokio::{sync::broadcast, time};
+
+use crate::config::config::{Probe, Readiness, ReadyCheck};
+
+use super::{
+    confinement::Confinement,
+    notify::{Notification, NotifySocket},
+    probe,
+};
+
+// longest line of output matched against pattern
+const MAX_LINE_SIZE: usize = 65536;
+
+/// wait for program to become ready, prepared before it is spawned so no
+/// output or notification is missed
+pub enum ReadyWait {
+    Probe(Probe),
+    File(PathBuf),
+    Stdout(Regex, broadcast::Receiver<String>),
+    Notify(broadcast::Receiver<Notification>),
+}
+
+impl ReadyWait {
+    pub fn prepare(
+        conf: &Readiness,
+        output: &broadcast::Sender<String>,
+        notify: Option<&NotifySocket>,
+    ) -> Result<Self> {
+        Ok(match &conf.check {
+            ReadyCheck::Exec { command } => Self::Probe(Probe::Exec {
+                command: command.clone(),
+            }),
+            ReadyCheck::Tcp { address } => Self::Probe(Probe::Tcp {
+                address: address.clone(),
+            }),
+            ReadyCheck::Http { url } => Self::Probe(Probe::Http { url: url.clone() }),
+            ReadyCheck::File { path } => Self::File(PathBuf::from(path)),
+            ReadyCheck::Stdout { pattern } => {
+                Self::Stdout(Regex::new(pattern)?, output.subscribe())
+            }
+            ReadyCheck::Notify {} => Self::Notify(
+                notify
+                    .ok_or_else(|| anyhow!("notify socket is not available"))?
+                    .subscribe(),
+            ),
+        })
+    }
+
+    /// resolve once program running as pid is ready, callers bound it by a
+    /// timeout
+    pub async fn wait(
+        self,
+        pid: u32,
+        confinement: impl Fn() -> Result<Confinement>,
+        interval: Duration,
+    ) {
+        match self {
+            Self::Probe(p) => {
+                while let Err(e) = probe::check(&p, &confinement, interval).await {
+                    debug!("program is not ready: {e:#}");
+                    time::sleep(interval).await;
+                }
+            }
+            Self::File(path) => {
+                while !path.exists() {
+                    time::sleep(interval).await;
+                }
+            }
+            Self::Stdout(pattern, output) => wait_line(pattern, output).await,
+            Self::Notify(mut messages) => loop {
+                match messages.recv().await {
+                    Ok(n) if n.message == "READY=1" && n.sent_by(pid) => return,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+                }
+            },
+        }
+    }
+}
+
+async fn wait_line(pattern: Regex, mut output: broadcast::Receiver<String>) {
+    let mut line = String::new();
+    loop {
+        let data = match output.recv().await {
+            Ok(data) => data,
+            // a line may be lost, later lines still match
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                line.clear();
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+        };
+        for chunk in data.split_inclusive('\n') {
+            if line.len() < MAX_LINE_SIZE {
+                line.push_str(chunk);
+            }
+            if chunk.ends_with('\n') {
+                if pattern.is_match(&line) {
+                    return;
+                }
+                line.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // only probes are confined
+    fn unconfined() -> Result<Confinement> {
+        Err(anyhow!("not a probe"))
+    }
+
+    #[tokio::test]
+    async fn async_ready_wait_test() {
+        let (output, _) = broadcast::channel(16);
+        let conf = Readiness {
+            check: ReadyCheck::Stdout {
+                pattern: "^listening on \\d+".to_string(),
+            },
+            timeout: 1,
+            interval: 1,
+        };
+        let ready = ReadyWait::prepare(&conf, &output, None).unwrap();
+        output.send("starting\nlisten".to_string()).unwrap();
+        output.send("ing on 8080\n".to_string()).unwrap();
+        time::timeout(
+            Duration::from_secs(1),
+            ready.wait(0, unconfined, Duration::from_secs(1)),
+        )
+        .await
+        .unwrap();
+
+        let ready = ReadyWait::prepare(&conf, &output, None).unwrap();
+        output.send("listening on port\n".to_string()).unwrap();
+        assert!(time::timeout(
+            Duration::from_millis(100),
+            ready.wait(0, unconfined, Duration::from_secs(1))
+        )
+        .await
+        .is_err());
+
+        let conf = Readiness {
+            check: ReadyCheck::Notify {},
+            ..conf
+        };
+        assert!(ReadyWait::prepare(&conf, &output, None).is_err());
+    }
+}
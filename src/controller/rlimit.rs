@@ -0,0 +1,156 @@
+use std::{collections::BTreeMap, io};
+
+use anyhow::{anyhow, Result};
+
+use crate::config::config::{Rlimit, RlimitResource, RlimitValue};
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+/// rlimits checked against limits of sup, applied in program by pre_exec
+pub struct Rlimits(Vec<(Resource, libc::rlimit)>);
+
+impl Rlimits {
+    /// hard limits can only be raised with CAP_SYS_RESOURCE, check them
+    /// here so the error tells which limit can't be set
+    pub fn new(conf: &BTreeMap<RlimitResource, Rlimit>) -> Result<Self> {
+        let privileged = unsafe { libc::geteuid() } == 0;
+        let mut limits = Vec::with_capacity(conf.len());
+        for (&resource, limit) in conf {
+            let res = resource_of(resource);
+            let wanted = libc::rlimit {
+                rlim_cur: value_of(limit.soft()),
+                rlim_max: value_of(limit.hard()),
+            };
+            let current = get(res).map_err(|e| anyhow!("get rlimit {} failed: {}", resource, e))?;
+            if !privileged && wanted.rlim_max > current.rlim_max {
+                return Err(anyhow!(
+                    "raise hard rlimit {} to {} above {} needs root",
+                    resource,
+                    limit.hard(),
+                    format_value(current.rlim_max)
+                ));
+            }
+            // the kernel caps open files at fs.nr_open even for root
+            if resource == RlimitResource::Nofile {
+                let nr_open = nr_open()?;
+                if wanted.rlim_max > nr_open {
+                    return Err(anyhow!(
+                        "hard rlimit {} {} is above fs.nr_open {}",
+                        resource,
+                        limit.hard(),
+                        nr_open
+                    ));
+                }
+            }
+            limits.push((res, wanted));
+        }
+        Ok(Self(limits))
+    }
+
+    /// set limits in program before exec, errors only carry errno so new
+    /// checks what it can first
+    pub fn apply(&self) -> io::Result<()> {
+        for (res, limit) in &self.0 {
+            if unsafe { libc::setrlimit(*res, limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get(res: Resource) -> io::Result<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(res, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit)
+}
+
+fn nr_open() -> Result<libc::rlim_t> {
+    let v = std::fs::read_to_string("/proc/sys/fs/nr_open")
+        .map_err(|e| anyhow!("read fs.nr_open failed: {}", e))?;
+    v.trim()
+        .parse()
+        .map_err(|e| anyhow!("parse fs.nr_open {:?} failed: {}", v.trim(), e))
+}
+
+fn value_of(v: RlimitValue) -> libc::rlim_t {
+    match v {
+        RlimitValue::Limited(v) => v as libc::rlim_t,
+        RlimitValue::Unlimited => libc::RLIM_INFINITY,
+    }
+}
+
+fn format_value(v: libc::rlim_t) -> String {
+    match v {
+        libc::RLIM_INFINITY => "unlimited".to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn resource_of(r: RlimitResource) -> Resource {
+    match r {
+        RlimitResource::As => libc::RLIMIT_AS,
+        RlimitResource::Core => libc::RLIMIT_CORE,
+        RlimitResource::Cpu => libc::RLIMIT_CPU,
+        RlimitResource::Data => libc::RLIMIT_DATA,
+        RlimitResource::Fsize => libc::RLIMIT_FSIZE,
+        RlimitResource::Locks => libc::RLIMIT_LOCKS,
+        RlimitResource::Memlock => libc::RLIMIT_MEMLOCK,
+        RlimitResource::Msgqueue => libc::RLIMIT_MSGQUEUE,
+        RlimitResource::Nice => libc::RLIMIT_NICE,
+        RlimitResource::Nofile => libc::RLIMIT_NOFILE,
+        RlimitResource::Nproc => libc::RLIMIT_NPROC,
+        RlimitResource::Rss => libc::RLIMIT_RSS,
+        RlimitResource::Rtprio => libc::RLIMIT_RTPRIO,
+        RlimitResource::Rttime => libc::RLIMIT_RTTIME,
+        RlimitResource::Sigpending => libc::RLIMIT_SIGPENDING,
+        RlimitResource::Stack => libc::RLIMIT_STACK,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+
+    #[test]
+    fn apply_rlimits_test() {
+        let conf = BTreeMap::from([
+            (
+                RlimitResource::Nofile,
+                Rlimit::Pair {
+                    soft: RlimitValue::Limited(64),
+                    hard: RlimitValue::Limited(128),
+                },
+            ),
+            (RlimitResource::Core, Rlimit::Both(RlimitValue::Limited(0))),
+        ]);
+        let limits = Rlimits::new(&conf).unwrap();
+        let mut cmd = std::process::Command::new("/bin/sh");
+        cmd.args(["-c", "ulimit -n; ulimit -Hn; ulimit -c"]);
+        unsafe {
+            cmd.pre_exec(move || limits.apply());
+        }
+        let out = cmd.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "64\n128\n0\n");
+
+        // unlimited is above fs.nr_open for root too
+        let conf = BTreeMap::from([(RlimitResource::Nofile, Rlimit::Both(RlimitValue::Unlimited))]);
+        let e = Rlimits::new(&conf).err().unwrap().to_string();
+        assert!(e.contains("nofile unlimited"), "{}", e);
+
+        if unsafe { libc::geteuid() } != 0 {
+            let conf =
+                BTreeMap::from([(RlimitResource::Nofile, Rlimit::Both(RlimitValue::Unlimited))]);
+            assert!(Rlimits::new(&conf).is_err());
+        }
+    }
+}
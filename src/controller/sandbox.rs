@@ -0,0 +1,394 @@
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io,
+    os::unix::{ffi::OsStrExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::config::config::Sandbox as SandboxConf;
+
+use super::sys::{check, write_file};
+
+/// namespaces and restrictions applied in program by pre_exec, sup without
+/// root unshares a user namespace mapping its own ids to get them
+#[derive(Debug)]
+pub struct Sandbox {
+    flags: libc::c_int,
+    // contents of uid_map and gid_map
+    id_maps: Option<(String, String)>,
+    read_only: Vec<CString>,
+    // read only paths and mount points below them, remounted after binding
+    remounts: Vec<CString>,
+    root: Option<CString>,
+    // entered after chroot
+    work_dir: CString,
+    no_new_privs: bool,
+}
+
+impl Sandbox {
+    pub fn new(conf: &SandboxConf, work_dir: &str) -> Result<Self> {
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        Self::with_ids(conf, work_dir, uid, gid)
+    }
+
+    fn with_ids(
+        conf: &SandboxConf,
+        work_dir: &str,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+    ) -> Result<Self> {
+        let mut flags = 0;
+        // chroot needs CAP_SYS_CHROOT, which unprivileged sup only has in
+        // its own user namespace
+        if !conf.read_only_paths.is_empty() || conf.root.is_some() {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if conf.private_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        let id_maps = match (flags, uid) {
+            (0, _) | (_, 0) => None,
+            _ => {
+                flags |= libc::CLONE_NEWUSER;
+                Some((format!("{0} {0} 1", uid), format!("{0} {0} 1", gid)))
+            }
+        };
+
+        let mut read_only = Vec::with_capacity(conf.read_only_paths.len());
+        let mut remounts = vec![];
+        if !conf.read_only_paths.is_empty() {
+            // remount of a bind only makes its top mount read only
+            let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+            for p in &conf.read_only_paths {
+                let path = fs::canonicalize(p)
+                    .map_err(|e| anyhow!("read only path {} is not accessible: {}", p, e))?;
+                read_only.push(CString::new(path.as_os_str().as_bytes())?);
+                for mount in mounts_below(&mountinfo, &path) {
+                    remounts.push(CString::new(mount.as_os_str().as_bytes())?);
+                }
+            }
+        }
+        let root = match &conf.root {
+            Some(root) => {
+                if !Path::new(root)
+                    .join(work_dir.trim_start_matches('/'))
+                    .is_dir()
+                {
+                    return Err(anyhow!(
+                        "work dir {} does not exist in root {}",
+                        work_dir,
+                        root
+                    ));
+                }
+                Some(CString::new(root.as_bytes())?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            flags,
+            id_maps,
+            read_only,
+            remounts,
+            root,
+            work_dir: CString::new(Path::new(work_dir).as_os_str().as_bytes())?,
+            no_new_privs: conf.no_new_privs,
+        })
+    }
+
+    /// work dir is entered by sandbox after chroot
+    pub fn chroot(&self) -> bool {
+        self.root.is_some()
+    }
+
+    /// namespaces and root of program running as pid, joined by its exec
+    /// probes instead of unsharing new ones
+    pub fn join(&self, pid: u32) -> Result<Joined> {
+        let mut namespaces = vec![];
+        // user namespace owns the others, so it is joined first
+        for (flag, name) in [
+            (libc::CLONE_NEWUSER, "user"),
+            (libc::CLONE_NEWNS, "mnt"),
+            (libc::CLONE_NEWNET, "net"),
+        ] {
+            if self.flags & flag != 0 {
+                let path = format!("/proc/{}/ns/{}", pid, name);
+                let ns = File::open(&path).map_err(|e| anyhow!("open {} failed: {}", path, e))?;
+                namespaces.push((ns, flag));
+            }
+        }
+        let root = match &self.root {
+            Some(_) => {
+                let path = format!("/proc/{}/root", pid);
+                Some(File::open(&path).map_err(|e| anyhow!("open {} failed: {}", path, e))?)
+            }
+            None => None,
+        };
+        Ok(Joined {
+            namespaces,
+            root,
+            work_dir: self.work_dir.clone(),
+            no_new_privs: self.no_new_privs,
+        })
+    }
+
+    /// enter namespaces and mount views in program before exec, after
+    /// rlimits and before credential is dropped
+    pub fn apply(&self) -> io::Result<()> {
+        if self.flags != 0 {
+            check(unsafe { libc::unshare(self.flags) })?;
+        }
+        if let Some((uid_map, gid_map)) = &self.id_maps {
+            write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+            // gid_map of unprivileged user needs setgroups denied
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+        }
+        if self.flags & libc::CLONE_NEWNS != 0 {
+            // mounts below are not propagated back to sup
+            check(unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                )
+            })?;
+        }
+        for path in &self.read_only {
+            bind(path)?;
+        }
+        for path in &self.remounts {
+            remount_read_only(path)?;
+        }
+        if let Some(root) = &self.root {
+            check(unsafe { libc::chroot(root.as_ptr()) })?;
+            check(unsafe { libc::chdir(self.work_dir.as_ptr()) })?;
+        }
+        if self.no_new_privs {
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+}
+
+/// namespaces and root of a running program, opened by sup so a probe can
+/// enter them before exec
+#[derive(Debug)]
+pub struct Joined {
+    namespaces: Vec<(File, libc::c_int)>,
+    root: Option<File>,
+    work_dir: CString,
+    no_new_privs: bool,
+}
+
+impl Joined {
+    /// joining mounts leaves probe in their root, so work dir is entered
+    /// again, after chroot if program has one
+    pub fn apply(&self) -> io::Result<()> {
+        for (ns, flag) in &self.namespaces {
+            check(unsafe { libc::setns(ns.as_raw_fd(), *flag) })?;
+        }
+        if let Some(root) = &self.root {
+            check(unsafe { libc::fchdir(root.as_raw_fd()) })?;
+            check(unsafe { libc::chroot(c".".as_ptr()) })?;
+        }
+        check(unsafe { libc::chdir(self.work_dir.as_ptr()) })?;
+        if self.no_new_privs {
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        }
+        Ok(())
+    }
+}
+
+/// bind path with mounts below it onto itself, so it can be remounted
+fn bind(path: &CString) -> io::Result<()> {
+    check(unsafe {
+        libc::mount(
+            path.as_ptr(),
+            path.as_ptr(),
+            std::ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            std::ptr::null(),
+        )
+    })?;
+    Ok(())
+}
+
+/// flags of the existing mount are kept, a user namespace may not clear them
+fn remount_read_only(path: &CString) -> io::Result<()> {
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::statvfs(path.as_ptr(), &mut st) })?;
+    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+    for (st_flag, ms_flag) in [
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if st.f_flag & st_flag != 0 {
+            flags |= ms_flag;
+        }
+    }
+    check(unsafe {
+        libc::mount(
+            std::ptr::null(),
+            path.as_ptr(),
+            std::ptr::null(),
+            flags,
+            std::ptr::null(),
+        )
+    })?;
+    Ok(())
+}
+
+/// path and mount points below it in mountinfo, in order of mounting
+fn mounts_below(mountinfo: &str, path: &Path) -> Vec<PathBuf> {
+    let mut mounts = vec![path.to_path_buf()];
+    for line in mountinfo.lines() {
+        let point = match line.split(' ').nth(4) {
+            Some(point) => PathBuf::from(unescape(point)),
+            None => continue,
+        };
+        if point.starts_with(path) && !mounts.contains(&point) {
+            mounts.push(point);
+        }
+    }
+    mounts
+}
+
+/// mountinfo escapes space, tab, newline and backslash in octal
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        match rest
+            .get(i + 1..i + 4)
+            .and_then(|o| u8::from_str_radix(o, 8).ok())
+        {
+            Some(c) => {
+                out.push(c as char);
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::{fs::PermissionsExt, process::CommandExt};
+
+    // changing ids clears dumpable, which hides /proc/self
+    unsafe fn become_nobody() -> io::Result<()> {
+        check(libc::setgroups(0, std::ptr::null()))?;
+        check(libc::setgid(65534))?;
+        check(libc::setuid(65534))?;
+        check(libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0))?;
+        Ok(())
+    }
+
+    // containers may refuse namespaces, sandbox can't be tested there
+    fn unshare_refused(flags: libc::c_int, nobody: bool) -> bool {
+        let mut cmd = std::process::Command::new("/bin/true");
+        unsafe {
+            cmd.pre_exec(move || {
+                if nobody {
+                    become_nobody()?;
+                }
+                check(libc::unshare(flags))?;
+                Ok(())
+            });
+        }
+        matches!(
+            cmd.status().map_err(|e| e.raw_os_error()),
+            Err(Some(libc::EPERM | libc::EINVAL))
+        )
+    }
+
+    #[test]
+    fn sandbox_test() {
+        let dir = std::env::temp_dir().join(format!("sup-sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let conf = SandboxConf {
+            read_only_paths: vec![dir.to_str().unwrap().to_string()],
+            private_network: true,
+            root: None,
+            no_new_privs: true,
+        };
+        // run as nobody if possible, so sandbox is set up by a user namespace
+        let nobody = unsafe { libc::geteuid() } == 0;
+        let sandbox = match nobody {
+            true => Sandbox::with_ids(&conf, "/", 65534, 65534).unwrap(),
+            false => Sandbox::new(&conf, "/").unwrap(),
+        };
+        if unshare_refused(sandbox.flags, nobody) {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let mut cmd = std::process::Command::new("/bin/sh");
+        cmd.args([
+            "-c",
+            &format!(
+                "touch {}/f 2>/dev/null && echo rw || echo ro; \
+                 tail -n +3 /proc/self/net/dev | wc -l; \
+                 grep NoNewPrivs /proc/self/status",
+                dir.display()
+            ),
+        ]);
+        unsafe {
+            cmd.pre_exec(move || {
+                if nobody {
+                    become_nobody()?;
+                }
+                sandbox.apply()
+            });
+        }
+        let out = cmd.output().unwrap();
+        let out = String::from_utf8_lossy(&out.stdout);
+        let lines: Vec<&str> = out.lines().map(str::trim).collect();
+        assert_eq!(lines, vec!["ro", "1", "NoNewPrivs:\t1"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chroot_namespace_test() {
+        let conf = SandboxConf {
+            root: Some("/".to_string()),
+            ..Default::default()
+        };
+        let sandbox = Sandbox::with_ids(&conf, "/", 1000, 1000).unwrap();
+        assert_eq!(sandbox.flags, libc::CLONE_NEWNS | libc::CLONE_NEWUSER);
+        let sandbox = Sandbox::with_ids(&conf, "/", 0, 0).unwrap();
+        assert_eq!(sandbox.flags, libc::CLONE_NEWNS);
+    }
+
+    #[test]
+    fn mounts_below_test() {
+        let mountinfo = "22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw
+23 22 0:5 / /srv/data rw - tmpfs tmpfs rw
+24 23 0:6 / /srv/data/my\\040dir rw - tmpfs tmpfs rw
+25 22 0:7 / /srv/database rw - tmpfs tmpfs rw";
+        assert_eq!(
+            mounts_below(mountinfo, Path::new("/srv/data")),
+            vec![
+                PathBuf::from("/srv/data"),
+                PathBuf::from("/srv/data/my dir")
+            ]
+        );
+    }
+}
@@ -1,110 +1,906 @@
-use std::path::Path;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
-use log::{debug, error, info};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info, warn};
 use tokio::{
     fs,
     io::{AsyncReadExt, AsyncWriteExt},
     net::{UnixListener, UnixStream},
+    sync::{broadcast, watch, Mutex, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+    time,
 };
 
+use crate::{config::config::Config, rotater::rotater::Rotater};
+
 use super::{
-    command::{Request, Response},
+    activation::Sockets,
+    cgroup::Cgroup,
+    command::{AttachInput, Command, Request, Response, ResponseBody},
     controller::ProcessController,
+    error::{Error, ErrorKind},
+    http::HttpApi,
+    listener::EventListener,
+    protocol::{read_frame, write_frame, Hello, HelloAck, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+    tail::{LogTail, Utf8Decoder},
 };
 
+const ROTATE_CHANNEL_LENGTH: usize = 16;
+// connections of control socket and http api together
+pub(super) const MAX_CONNECTIONS: usize = 64;
+// idle connection is closed if no frame arrives in READ_TIMEOUT
+pub(super) const READ_TIMEOUT: Duration = Duration::from_secs(60);
+pub(super) const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const REFUSE_TIMEOUT: Duration = Duration::from_secs(1);
+pub(super) const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 pub struct Server {
     listener: UnixListener,
-    controller: ProcessController,
+    socket_path: PathBuf,
+    controller: Arc<ProcessController>,
+    // taken by run
+    http: Mutex<Option<HttpApi>>,
+    http_addr: Option<SocketAddr>,
+    rotater: Mutex<Option<JoinHandle<()>>>,
+    listeners: Mutex<Vec<JoinHandle<()>>>,
+    // stops rotater and listeners
+    workers_shutdown: watch::Sender<bool>,
+    connections: Arc<Semaphore>,
+    // set by exit, shutdown is sent once the exit response is written
+    exiting: AtomicBool,
+    shutdown: watch::Sender<bool>,
 }
 
 impl Server {
-    pub async fn new<P: AsRef<Path>>(socket_path: P) -> Result<Self> {
-        if socket_path.as_ref().exists() && UnixStream::connect(&socket_path).await.is_err() {
-            fs::remove_file(&socket_path.as_ref()).await?;
+    pub async fn new(cfg: Config) -> Result<Self> {
+        let socket_path = Path::new(&cfg.sup.socket).to_path_buf();
+        if socket_path.exists() && UnixStream::connect(&socket_path).await.is_err() {
+            fs::remove_file(&socket_path).await?;
         }
-        let listener = UnixListener::bind(&socket_path).context(format!(
-            "bind socket path {:?} failed",
-            socket_path.as_ref()
-        ))?;
+        let listener = UnixListener::bind(&socket_path)
+            .context(format!("bind socket path {:?} failed", socket_path))?;
+
+        let http = match cfg.sup.http {
+            Some(conf) => Some(HttpApi::bind(conf).await?),
+            None => None,
+        };
+        let http_addr = http.as_ref().and_then(|http| http.local_addr());
+
+        let mut rotater = Rotater::new(ROTATE_CHANNEL_LENGTH)?;
+        let sockets = Sockets::open(&cfg.program.socket)?;
+        let cgroup = cfg.program.process.cgroup.as_ref().and_then(|conf| {
+            match Cgroup::create(&cfg.program.name, conf) {
+                Ok(cgroup) => {
+                    info!(
+                        "program {} runs in cgroup {:?}",
+                        cfg.program.name,
+                        cgroup.path()
+                    );
+                    Some(cgroup)
+                }
+                Err(e) => {
+                    warn!("program {} runs without cgroup: {e:#}", cfg.program.name);
+                    None
+                }
+            }
+        });
+        let controller = ProcessController::new(cfg.program, sockets, cgroup, rotater.handle());
+        let (workers_shutdown, shutdown_recv) = watch::channel(false);
+        let listeners = cfg
+            .listener
+            .into_iter()
+            .map(|conf| {
+                let listener = EventListener::new(conf, controller.subscribe_events());
+                tokio::spawn(listener.run(shutdown_recv.clone()))
+            })
+            .collect();
+        let rotater = tokio::spawn(async move { rotater.run(shutdown_recv).await });
+        controller.auto_start().await?;
 
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            socket_path,
+            controller,
+            http: Mutex::new(http),
+            http_addr,
+            rotater: Mutex::new(Some(rotater)),
+            listeners: Mutex::new(listeners),
+            workers_shutdown,
+            connections: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+            exiting: AtomicBool::new(false),
+            shutdown: watch::channel(false).0,
+        })
     }
 
-    pub async fn run(&self) {
+    /// serve each connection in its own task until exit
+    pub async fn run(self: Arc<Self>) {
+        let mut shutdown = self.shutdown.subscribe();
+        let http = self
+            .http
+            .lock()
+            .await
+            .take()
+            .map(|http| tokio::spawn(http.run(self.clone(), self.shutdown.subscribe())));
         loop {
-            match self.listener.accept().await {
-                Ok((mut socket, addr)) => {
-                    info!("accept socket from {:?}", addr);
-                    if let Err(e) = Self::handle_socket(&mut socket).await {
-                        error!("handle socket failed: {e}")
-                    };
-                }
+            let accepted = tokio::select! {
+                biased;
+                _ = shutdown.changed() => break,
+                accepted = self.listener.accept() => accepted,
+            };
+            let (mut socket, addr) = match accepted {
+                Ok(accepted) => accepted,
                 Err(e) => {
                     error!("accept socket failed: {e}");
+                    continue;
+                }
+            };
+
+            info!("accept socket from {:?}", addr);
+            let permit = match self.admit() {
+                Some(permit) => permit,
+                None => {
+                    tokio::spawn(Self::refuse(socket));
+                    continue;
                 }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle_socket(&mut socket).await {
+                    error!("handle socket failed: {e}")
+                };
+                drop(permit);
+            });
+        }
+        if let Some(http) = http {
+            if let Err(e) = http.await {
+                error!("wait http api failed: {e}");
             }
         }
+        info!("server exit");
     }
 
-    async fn handle_socket(socket: &mut UnixStream) -> Result<()> {
-        let mut buf = String::new();
-        socket.read_to_string(&mut buf).await?;
-        debug!("read socket done {}", buf);
+    /// tcp address http api listens on
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.http_addr
+    }
+
+    pub(super) fn controller(&self) -> &ProcessController {
+        &self.controller
+    }
+
+    /// permit of a new connection, none if connections exceed the cap
+    pub(super) fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        self.connections.clone().try_acquire_owned().ok()
+    }
 
-        let req: Request = buf.as_bytes().to_vec().into();
-        let res: Vec<u8> = Self::handle_command(req).into();
-        debug!("handle request done {:?}", res);
-        socket.write_all(&res).await?;
+    async fn handle_socket(&self, socket: &mut UnixStream) -> Result<()> {
+        if !Self::handshake(socket).await? {
+            return Ok(());
+        }
+
+        while let Some(frame) = with_timeout(READ_TIMEOUT, read_frame(socket)).await? {
+            let mut exit = false;
+            let res = match serde_json::from_slice::<Request>(&frame) {
+                Ok(req) => {
+                    debug!("read request done {:?}", req);
+                    let id = req.id;
+                    exit = req.cmd == Command::Exit;
+                    if let Command::Tail {
+                        follow,
+                        lines,
+                        stderr,
+                    } = req.cmd
+                    {
+                        self.tail(socket, id, follow, lines, stderr).await?;
+                        continue;
+                    }
+                    if req.cmd == Command::Events {
+                        self.events(socket, id).await?;
+                        return Ok(());
+                    }
+                    if req.cmd == Command::Attach {
+                        if self.attach(socket, id).await? {
+                            continue;
+                        }
+                        // client closed while attached
+                        return Ok(());
+                    }
+                    self.handle_command(req).await.with_id(id)
+                }
+                Err(e) => Self::response(ResponseBody::Error {
+                    kind: ErrorKind::BadRequest,
+                    message: format!("decode request failed: {e}"),
+                })
+                .with_id(request_id(&frame)),
+            };
+            debug!("handle request done {:?}", res);
+            with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await?;
+            if exit && self.exiting.load(Ordering::SeqCst) {
+                self.shutdown.send_replace(true);
+                break;
+            }
+        }
         socket.shutdown().await?;
         debug!("write socket done",);
         Ok(())
     }
 
-    fn start() -> Response {
+    /// reject connection over the cap right away, it holds no permit and is
+    /// only kept until client has read the rejection, closing it with hello
+    /// unread would reset it
+    async fn refuse(mut socket: UnixStream) {
+        let ack = HelloAck::Rejected {
+            kind: ErrorKind::TooManyConnections,
+            message: format!("connections exceed {}", MAX_CONNECTIONS),
+        };
+        warn!("reject client: {:?}", ack);
+        if let Err(e) = with_timeout(REFUSE_TIMEOUT, write_frame(&mut socket, &ack)).await {
+            error!("write rejection failed: {e}");
+            return;
+        }
+        let mut buf = [0; 64];
+        let drain = async { while matches!(socket.read(&mut buf).await, Ok(n) if n > 0) {} };
+        let _ = time::timeout(REFUSE_TIMEOUT, drain).await;
+    }
+
+    /// return false if client is rejected
+    async fn handshake(socket: &mut UnixStream) -> Result<bool> {
+        let hello = match with_timeout(READ_TIMEOUT, read_frame(socket)).await? {
+            Some(hello) => hello,
+            None => return Ok(false),
+        };
+        let ack = match serde_json::from_slice::<Hello>(&hello) {
+            Ok(hello) if hello.is_compatible() => HelloAck::Accepted {
+                version: PROTOCOL_VERSION,
+                sup_pid: process::id(),
+            },
+            Ok(hello) => HelloAck::Rejected {
+                kind: ErrorKind::IncompatibleVersion,
+                message: format!(
+                    "client protocol version {} is not in [{}, {}]",
+                    hello.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                ),
+            },
+            Err(e) => HelloAck::Rejected {
+                kind: ErrorKind::BadRequest,
+                message: format!("decode hello failed: {e}"),
+            },
+        };
+
+        let accepted = matches!(ack, HelloAck::Accepted { .. });
+        if !accepted {
+            warn!("reject client: {:?}", ack);
+        }
+        with_timeout(WRITE_TIMEOUT, write_frame(socket, &ack)).await?;
+        Ok(accepted)
+    }
+
+    async fn start(&self) -> Response {
         info!("starting program");
-        // if program is running, return
-        //
-        Response::new("start success".to_string(), None)
+        let res = self.controller.exec_cmd(Command::Start).await;
+        Self::result(Command::Start, "start", res)
+    }
+    async fn stop(&self) -> Response {
+        let res = self.controller.exec_cmd(Command::Stop).await;
+        Self::result(Command::Stop, "stop", res)
+    }
+    async fn restart(&self) -> Response {
+        let res = self.controller.exec_cmd(Command::Restart).await;
+        Self::result(Command::Restart, "restart", res)
     }
-    fn stop() -> Response {
-        Response::new("stop success".to_string(), None)
+    async fn kill(&self) -> Response {
+        let res = self.controller.exec_cmd(Command::Kill).await;
+        Self::result(Command::Kill, "kill", res)
     }
-    fn restart() -> Response {
-        Response::new("restart success".to_string(), None)
+    async fn reload(&self) -> Response {
+        let res = self.controller.exec_cmd(Command::Reload).await;
+        Self::result(Command::Reload, "reload", res)
     }
-    fn kill() -> Response {
-        Response::new("kill success".to_string(), None)
+    async fn rotate(&self) -> Response {
+        let res = self.controller.exec_cmd(Command::Rotate).await;
+        Self::result(Command::Rotate, "rotate", res)
     }
-    fn reload() -> Response {
-        Response::new("reload success".to_string(), None)
+    /// stream last lines of log to client as output frames, in follow mode
+    /// streaming stops once client closes the connection
+    async fn tail(
+        &self,
+        socket: &mut UnixStream,
+        id: u64,
+        follow: bool,
+        lines: usize,
+        stderr: bool,
+    ) -> Result<()> {
+        let cmd = Command::Tail {
+            follow,
+            lines,
+            stderr,
+        };
+        let tail = match self.controller.log_path(stderr) {
+            Ok(path) => LogTail::open(&path, lines).await,
+            Err(e) => Err(e),
+        };
+        let mut tail = match tail {
+            Ok(tail) => tail,
+            Err(e) => {
+                let res = Self::result(cmd, "tail", Err(e)).with_id(id);
+                return with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await;
+            }
+        };
+
+        let mut decoder = Utf8Decoder::default();
+        loop {
+            let data = tail.read().await?;
+            if !data.is_empty() {
+                let data = decoder.decode(&data);
+                // a cut character alone waits for the next read
+                if !data.is_empty() {
+                    let res = Self::response(ResponseBody::Output { data }).with_id(id);
+                    with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await?;
+                }
+                continue;
+            }
+            if !follow {
+                let data = decoder.finish();
+                if !data.is_empty() {
+                    let res = Self::response(ResponseBody::Output { data }).with_id(id);
+                    with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await?;
+                }
+                break;
+            }
+
+            // client sends nothing while following, readable means closed
+            let mut buf = [0; 1];
+            tokio::select! {
+                _ = time::sleep(TAIL_POLL_INTERVAL) => {}
+                _ = socket.read(&mut buf) => return Ok(()),
+            }
+        }
+
+        let res = Self::result(cmd, "tail", Ok(None)).with_id(id);
+        with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await
     }
-    fn status() -> Response {
-        Response::new("get status success".to_string(), None)
+
+    /// forward program output to client and input frames to program stdin
+    /// until client detaches, return false if client closed instead
+    async fn attach(&self, socket: &mut UnixStream, id: u64) -> Result<bool> {
+        if let Err(e) = self.controller.check_attach() {
+            let res = Self::result(Command::Attach, "attach", Err(e)).with_id(id);
+            with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await?;
+            return Ok(true);
+        }
+
+        let mut output = self.controller.subscribe_output();
+        let (mut rd, mut wr) = socket.split();
+        // read_frame is not cancel safe, input is read by one future living
+        // through the whole attach, it returns right after a detach frame
+        let input = async {
+            while let Some(frame) = read_frame(&mut rd).await? {
+                match serde_json::from_slice::<AttachInput>(&frame)
+                    .context("decode attach input failed")?
+                {
+                    AttachInput::Input { data } => self.controller.write_stdin(&data).await?,
+                    AttachInput::Detach => return Ok(true),
+                }
+            }
+            Ok(false)
+        };
+        tokio::pin!(input);
+
+        let res = loop {
+            tokio::select! {
+                res = &mut input => break res,
+                data = output.recv() => {
+                    let data = match data {
+                        Ok(data) => data,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            warn!("attached client lagged, {n} outputs dropped");
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break Ok(true),
+                    };
+                    let res = Self::response(ResponseBody::Output { data }).with_id(id);
+                    with_timeout(WRITE_TIMEOUT, write_frame(&mut wr, &res)).await?;
+                }
+            }
+        };
+
+        let detached = match res {
+            Ok(false) => return Ok(false),
+            Ok(true) => Self::result(Command::Attach, "detach", Ok(self.controller.pid())),
+            Err(e) => Self::result(Command::Attach, "attach", Err(e)),
+        };
+        with_timeout(WRITE_TIMEOUT, write_frame(&mut wr, &detached.with_id(id))).await?;
+        Ok(true)
     }
-    fn exit() -> Response {
-        Response::new("exit success".to_string(), None)
+
+    /// stream events to client until it closes the connection
+    async fn events(&self, socket: &mut UnixStream, id: u64) -> Result<()> {
+        let mut events = self.controller.subscribe_events();
+        loop {
+            // client sends nothing while subscribed, readable means closed
+            let mut buf = [0; 1];
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = socket.read(&mut buf) => return Ok(()),
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("events client lagged, {n} events dropped");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let res = Self::response(ResponseBody::Event { event }).with_id(id);
+            with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await?;
+        }
+
+        let res = Self::result(Command::Events, "events", Ok(None)).with_id(id);
+        with_timeout(WRITE_TIMEOUT, write_frame(socket, &res)).await
     }
-    fn unknown() -> Response {
-        Response::new("unknown command".to_string(), None)
+
+    fn status(&self) -> Response {
+        Self::response(ResponseBody::Status {
+            programs: vec![self.controller.status()],
+        })
     }
 
-    fn handle_command(r: Request) -> Response {
-        if let Some(cmd) = r.cmd {
-            match cmd {
-                super::command::Command::Start => Self::start(),
-                super::command::Command::Stop => Self::stop(),
-                super::command::Command::Restart => Self::restart(),
-                super::command::Command::Kill => Self::kill(),
-                super::command::Command::Reload => Self::reload(),
-                super::command::Command::Exit => Self::exit(),
-                super::command::Command::Status => Self::status(),
+    /// stop program, wait for running rotations and listeners and remove socket,
+    /// accept loop exits after the response is sent
+    async fn exit(&self) -> Response {
+        info!("exiting sup");
+        if let Err(e) = self.controller.exec_cmd(Command::Exit).await {
+            return Self::result(Command::Exit, "exit", Err(e));
+        }
+
+        self.workers_shutdown.send_replace(true);
+        if let Some(rotater) = self.rotater.lock().await.take() {
+            if let Err(e) = rotater.await {
+                error!("wait rotater failed: {e}");
+            }
+        }
+        // listeners are given a while to handle events of exit
+        for listener in self.listeners.lock().await.drain(..) {
+            if let Err(e) = listener.await {
+                error!("wait listener failed: {e}");
             }
-        } else {
-            Self::unknown()
+        }
+
+        if let Err(e) = fs::remove_file(&self.socket_path).await {
+            error!("remove socket {:?} failed: {e}", self.socket_path);
+        }
+        self.exiting.store(true, Ordering::SeqCst);
+        Self::result(Command::Exit, "exit", Ok(None))
+    }
+    fn result(cmd: Command, name: &str, res: Result<Option<u32>>) -> Response {
+        match res {
+            Ok(pid) => Self::response(ResponseBody::Success {
+                command: cmd,
+                message: format!("{name} success"),
+                pid,
+            }),
+            Err(e) => Self::response(ResponseBody::Error {
+                kind: (&e).into(),
+                message: format!("{name} failed: {e}"),
+            }),
+        }
+    }
+
+    fn response(body: ResponseBody) -> Response {
+        Response::new(body, Some(process::id()))
+    }
+
+    pub(super) async fn handle_command(&self, r: Request) -> Response {
+        match r.cmd {
+            Command::Start => self.start().await,
+            Command::Stop => self.stop().await,
+            Command::Restart => self.restart().await,
+            Command::Kill => self.kill().await,
+            Command::Reload => self.reload().await,
+            Command::Exit => self.exit().await,
+            Command::Status => self.status(),
+            Command::Rotate => self.rotate().await,
+            Command::Tail { .. } => Self::result(
+                r.cmd,
+                "tail",
+                Err(Error::InvalidArgument("tail must be streamed".to_string()).into()),
+            ),
+            Command::Events => Self::result(
+                r.cmd,
+                "events",
+                Err(Error::InvalidArgument("events must be streamed".to_string()).into()),
+            ),
+            Command::Attach => Self::result(
+                r.cmd,
+                "attach",
+                Err(Error::InvalidArgument("attach must be streamed".to_string()).into()),
+            ),
         }
     }
 }
-impl Drop for Server {
-    fn drop(&mut self) {}
+
+/// id of a request which can not be decoded, 0 if it has none either
+fn request_id(frame: &[u8]) -> u64 {
+    serde_json::from_slice::<serde_json::Value>(frame)
+        .ok()
+        .and_then(|v| v.get("id")?.as_u64())
+        .unwrap_or(0)
+}
+
+async fn with_timeout<T>(timeout: Duration, f: impl Future<Output = Result<T>>) -> Result<T> {
+    match time::timeout(timeout, f).await {
+        Ok(res) => res,
+        Err(_) => Err(anyhow!("socket timeout after {:?}", timeout)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::controller::{client::Client, controller::ExitReason, event::EventKind};
+
+    /// create server supervising `sleep 30` in a temp dir named by test
+    async fn new_server(test: &str) -> (Server, PathBuf) {
+        new_server_with(test, "path = \"/bin/sleep\"\nargs = [\"30\"]", "").await
+    }
+
+    /// extra is appended to config, such as tables of sup
+    async fn new_server_with(test: &str, program: &str, extra: &str) -> (Server, PathBuf) {
+        let dir = env::temp_dir().join(format!("sup-server-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            format!(
+                "[sup]
+socket = \"{}/sup.sock\"
+
+[program.process]
+{}
+workDir = \"{}\"
+autoStart = true
+stopSeconds = 1
+
+[program.log]
+path = \"run.log\"
+
+{}
+",
+                dir.display(),
+                program,
+                dir.display(),
+                extra
+            ),
+        )
+        .unwrap();
+
+        let cfg = Config::new(config_path.to_str().unwrap()).unwrap();
+        (Server::new(cfg).await.unwrap(), dir)
+    }
+
+    #[tokio::test]
+    async fn stuck_client_test() {
+        let (server, dir) = new_server("stuck").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        // never sends hello
+        let _stuck = UnixStream::connect(&socket).await.unwrap();
+        let cli = Client::new(socket.to_str().unwrap().to_string());
+        let resp = time::timeout(
+            Duration::from_secs(5),
+            cli.request(Request::new(Command::Status)),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(matches!(resp.body(), ResponseBody::Status { .. }));
+
+        cli.request(Request::new(Command::Exit)).await.unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exit_test() {
+        let (server, dir) = new_server("exit").await;
+        let socket = dir.join("sup.sock");
+        let pid = server.controller.pid().unwrap();
+        let run = tokio::spawn(Arc::new(server).run());
+
+        let resp = Client::new(socket.to_str().unwrap().to_string())
+            .request(Request::new(Command::Exit))
+            .await
+            .unwrap();
+        assert_eq!(resp.to_string(), "exit success");
+
+        run.await.unwrap();
+        assert!(!socket.exists());
+        assert_ne!(unsafe { libc::kill(pid as i32, 0) }, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reject_incompatible_version_test() {
+        let (server, dir) = new_server("version").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        write_frame(&mut stream, &Hello { version: 0 })
+            .await
+            .unwrap();
+        let ack = read_frame(&mut stream).await.unwrap().unwrap();
+        assert!(matches!(
+            serde_json::from_slice(&ack).unwrap(),
+            HelloAck::Rejected {
+                kind: ErrorKind::IncompatibleVersion,
+                ..
+            }
+        ));
+        assert!(read_frame(&mut stream).await.unwrap().is_none());
+
+        Client::new(socket.to_str().unwrap().to_string())
+            .request(Request::new(Command::Exit))
+            .await
+            .unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn too_many_connections_test() {
+        let (server, dir) = new_server("cap").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        // connections which never send hello hold all permits
+        let mut stuck = vec![];
+        for _ in 0..MAX_CONNECTIONS {
+            stuck.push(UnixStream::connect(&socket).await.unwrap());
+        }
+        let cli = Client::new(socket.to_str().unwrap().to_string());
+        let e = time::timeout(
+            Duration::from_secs(5),
+            cli.request(Request::new(Command::Status)),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert!(e.to_string().contains("connections exceed"), "{e:#}");
+
+        drop(stuck);
+        loop {
+            if cli.request(Request::new(Command::Exit)).await.is_ok() {
+                break;
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bad_request_test() {
+        let (server, dir) = new_server("bad-request").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        write_frame(&mut stream, &Hello::new()).await.unwrap();
+        read_frame(&mut stream).await.unwrap().unwrap();
+        write_frame(&mut stream, &serde_json::json!({"id": 7, "cmd": "unknown"}))
+            .await
+            .unwrap();
+        let resp = read_frame(&mut stream).await.unwrap().unwrap();
+        let resp: Response = serde_json::from_slice(&resp).unwrap();
+        assert_eq!(resp.id(), 7);
+        assert!(matches!(
+            resp.body(),
+            ResponseBody::Error {
+                kind: ErrorKind::BadRequest,
+                ..
+            }
+        ));
+        drop(stream);
+
+        Client::new(socket.to_str().unwrap().to_string())
+            .request(Request::new(Command::Exit))
+            .await
+            .unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn attach_test() {
+        let (server, dir) =
+            new_server_with("attach", "path = \"/bin/cat\"\nstdin = true", "").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        let cli = Client::new(socket.to_str().unwrap().to_string());
+        let (mut stream, mut writer) = cli.attach().await.unwrap();
+        // server subscribes output before reading any input
+        writer.input(b"hello\n").await.unwrap();
+        let resp = stream.next().await.unwrap().unwrap();
+        assert_eq!(resp.to_string(), "hello\n");
+        writer.detach().await.unwrap();
+        loop {
+            let resp = stream.next().await.unwrap().unwrap();
+            if resp.is_terminal() {
+                assert!(resp.to_string().starts_with("detach success"));
+                break;
+            }
+        }
+
+        cli.request(Request::new(Command::Exit)).await.unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_test() {
+        let (server, dir) = new_server("events").await;
+        let socket = dir.join("sup.sock");
+        let run = tokio::spawn(Arc::new(server).run());
+
+        let cli = Arc::new(Client::new(socket.to_str().unwrap().to_string()));
+        let mut events = cli.events().await.unwrap();
+        let (send, mut recv) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Ok(Some(event)) = events.next().await {
+                if send.send(event).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        // subscription is done by server after the request is read,
+        // rotate until the first event is seen
+        let event = loop {
+            cli.request(Request::new(Command::Rotate)).await.unwrap();
+            if let Ok(event) = time::timeout(Duration::from_millis(200), recv.recv()).await {
+                break event.unwrap();
+            }
+        };
+        assert_eq!(event.program, "sleep");
+        assert_eq!(
+            event.kind,
+            EventKind::Rotated {
+                path: dir.join("run.log").to_str().unwrap().to_string()
+            }
+        );
+
+        cli.request(Request::new(Command::Kill)).await.unwrap();
+        let event = loop {
+            let event = recv.recv().await.unwrap();
+            if !matches!(event.kind, EventKind::Rotated { .. }) {
+                break event;
+            }
+        };
+        assert_eq!(
+            event.kind,
+            EventKind::Exited {
+                exit: ExitReason::Signal(libc::SIGKILL)
+            }
+        );
+
+        cli.request(Request::new(Command::Exit)).await.unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// send raw http/1.1 request, return status code and body
+    async fn http(addr: SocketAddr, method: &str, path: &str, auth: Option<&str>) -> (u16, String) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let auth = auth
+            .map(|a| format!("Authorization: {a}\r\n"))
+            .unwrap_or_default();
+        let req = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{auth}Content-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).await.unwrap();
+
+        let code = resp[9..12].parse().unwrap();
+        let body = resp.split_once("\r\n\r\n").unwrap().1.to_string();
+        (code, body)
+    }
+
+    #[tokio::test]
+    async fn http_api_test() {
+        let (server, dir) = new_server_with(
+            "http",
+            "path = \"/bin/sh\"\nargs = [\"-c\", \"echo hello; sleep 30\"]",
+            "[sup.http]\naddress = \"127.0.0.1:0\"\ntoken = \"secret\"",
+        )
+        .await;
+        let socket = dir.join("sup.sock");
+        let addr = server.http_addr().unwrap();
+        let server = Arc::new(server);
+        let run = tokio::spawn(server.clone().run());
+        let auth = Some("Bearer secret");
+
+        assert_eq!(http(addr, "GET", "/api/status", None).await.0, 401);
+        assert_eq!(
+            http(addr, "GET", "/api/status", Some("Bearer guess"))
+                .await
+                .0,
+            401
+        );
+        let (code, body) = http(addr, "GET", "/api/status", auth).await;
+        assert_eq!(code, 200);
+        assert!(body.contains(r#""state":"STARTING""#), "{body}");
+        assert_eq!(http(addr, "POST", "/api/status", auth).await.0, 405);
+        assert_eq!(http(addr, "GET", "/api/exit", auth).await.0, 404);
+
+        // body is chunked, wait for program to write its log
+        let mut tail = http(addr, "GET", "/api/tail?lines=1", auth).await;
+        while tail.1 == "0\r\n\r\n" {
+            time::sleep(Duration::from_millis(50)).await;
+            tail = http(addr, "GET", "/api/tail?lines=1", auth).await;
+        }
+        assert_eq!(tail, (200, "6\r\nhello\n\r\n0\r\n\r\n".to_string()));
+
+        // following tail ends once client is gone though log is idle
+        let mut follow = tokio::net::TcpStream::connect(addr).await.unwrap();
+        follow
+            .write_all(
+                b"GET /api/tail?follow=true HTTP/1.1\r\nHost: localhost\r\n\
+                  Authorization: Bearer secret\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = follow.read(&mut buf).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 200"));
+        assert!(server.connections.available_permits() < MAX_CONNECTIONS);
+        drop(follow);
+        time::timeout(Duration::from_secs(5), async {
+            while server.connections.available_permits() < MAX_CONNECTIONS {
+                time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let (code, body) = http(addr, "GET", "/metrics", auth).await;
+        assert_eq!(code, 200);
+        assert!(
+            body.contains("sup_program_up{program=\"sh\"} 1\n"),
+            "{body}"
+        );
+        let written = format!(
+            "sup_log_written_bytes_total{{program=\"sh\",log=\"{}\"}} 6\n",
+            dir.join("run.log").display()
+        );
+        assert!(body.contains(&written), "{body}");
+
+        let (code, body) = http(addr, "POST", "/api/stop", auth).await;
+        assert_eq!(code, 200);
+        assert!(body.contains(r#""message":"stop success""#), "{body}");
+        assert_eq!(http(addr, "POST", "/api/reload", auth).await.0, 409);
+
+        Client::new(socket.to_str().unwrap().to_string())
+            .request(Request::new(Command::Exit))
+            .await
+            .unwrap();
+        run.await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,24 @@
+use std::{ffi::CStr, io};
+
+// helpers for libc calls made between fork and exec, they must not allocate
+// or take locks
+
+/// error of a libc call returning -1, the returned value otherwise
+pub fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret)
+}
+
+/// write data to an existing file, such as cgroup.procs or uid_map
+pub fn write_file(path: &CStr, data: &[u8]) -> io::Result<()> {
+    let fd = check(unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) })?;
+    let n = unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) };
+    let e = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if n < 0 {
+        return Err(e);
+    }
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::{
+    io::{ErrorKind, SeekFrom},
+    os::unix::fs::MetadataExt,
+};
+
+use anyhow::{Context, Result};
+use log::info;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// LogTail reads a log from its last lines on, when the path is replaced by
+/// rotater the rest of old file is read before switching to the new one
+pub struct LogTail {
+    path: String,
+    file: File,
+    ino: u64,
+}
+
+impl LogTail {
+    pub async fn open(path: &str, lines: usize) -> Result<Self> {
+        let mut file = File::open(path)
+            .await
+            .context(format!("open log {} failed", path))?;
+        let ino = file.metadata().await?.ino();
+        let pos = Self::last_lines_offset(&mut file, lines).await?;
+        file.seek(SeekFrom::Start(pos)).await?;
+        Ok(Self {
+            path: path.to_string(),
+            file,
+            ino,
+        })
+    }
+
+    /// offset where the last n lines start, a trailing newline ends the
+    /// last line instead of starting an empty one
+    async fn last_lines_offset(file: &mut File, lines: usize) -> Result<u64> {
+        let len = file.metadata().await?.len();
+        if lines == 0 {
+            return Ok(len);
+        }
+
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        let mut pos = len;
+        let mut found = 0;
+        while pos > 0 {
+            let n = pos.min(READ_BUFFER_SIZE as u64) as usize;
+            pos -= n as u64;
+            file.seek(SeekFrom::Start(pos)).await?;
+            file.read_exact(&mut buf[..n]).await?;
+            for i in (0..n).rev() {
+                let offset = pos + i as u64;
+                if buf[i] != b'\n' || offset == len - 1 {
+                    continue;
+                }
+                found += 1;
+                if found == lines {
+                    return Ok(offset + 1);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// read data appended since last read, empty if there is nothing new
+    pub async fn read(&mut self) -> Result<Vec<u8>> {
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        let n = self.file.read(&mut buf).await?;
+        if n > 0 {
+            buf.truncate(n);
+            return Ok(buf);
+        }
+
+        let rest = match self.reopen_if_rotated().await? {
+            Some(rest) => rest,
+            None => return Ok(Vec::new()),
+        };
+        if !rest.is_empty() {
+            return Ok(rest);
+        }
+        let n = self.file.read(&mut buf).await?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// switch to the new file once path is replaced, rest of old file is
+    /// returned as it may have been written between the last read and rename
+    async fn reopen_if_rotated(&mut self) -> Result<Option<Vec<u8>>> {
+        let ino = match tokio::fs::metadata(&self.path).await {
+            Ok(meta) => meta.ino(),
+            // rotater creates the new file right after renaming
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if ino == self.ino {
+            return Ok(None);
+        }
+
+        let mut rest = Vec::new();
+        self.file.read_to_end(&mut rest).await?;
+        self.file = File::open(&self.path).await?;
+        self.ino = self.file.metadata().await?.ino();
+        info!("follow rotated log {}", self.path);
+        Ok(Some(rest))
+    }
+}
+
+/// Utf8Decoder turns chunks of output into text, a character cut at the
+/// end of a chunk is kept until the next chunk completes it
+#[derive(Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn decode(&mut self, data: &[u8]) -> String {
+        self.pending.extend_from_slice(data);
+        let cut = self.pending.len() - Self::incomplete_len(&self.pending);
+        let rest = self.pending.split_off(cut);
+        let text = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending = rest;
+        text
+    }
+
+    /// bytes kept for the next chunk, replaced as invalid once no chunk
+    /// follows
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending.clear();
+        text
+    }
+
+    /// length of a multibyte sequence cut at the end of data
+    fn incomplete_len(data: &[u8]) -> usize {
+        for (n, b) in data.iter().rev().take(3).enumerate() {
+            let len = match b {
+                0x80..=0xbf => continue,
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => return 0,
+            };
+            return if n + 1 < len { n + 1 } else { 0 };
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn async_tail_follow_rotation_test() {
+        let dir = std::env::temp_dir().join(format!("sup-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.log");
+        std::fs::write(&path, "1\n2\n3\n").unwrap();
+
+        let mut tail = LogTail::open(path.to_str().unwrap(), 2).await.unwrap();
+        assert_eq!(tail.read().await.unwrap(), b"2\n3\n");
+        assert!(tail.read().await.unwrap().is_empty());
+
+        std::fs::rename(&path, dir.join("run-20230317200700.log")).unwrap();
+        std::fs::write(&path, "4\n").unwrap();
+        assert_eq!(tail.read().await.unwrap(), b"4\n");
+
+        // written to old file after tail reached its end, then rotated
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"5\n")
+            .unwrap();
+        std::fs::rename(&path, dir.join("run-20230317200800.log")).unwrap();
+        std::fs::write(&path, "6\n").unwrap();
+        assert_eq!(tail.reopen_if_rotated().await.unwrap().unwrap(), b"5\n");
+        assert_eq!(tail.read().await.unwrap(), b"6\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn utf8_decoder_test() {
+        let text = "日志 ok".as_bytes();
+        let mut decoder = Utf8Decoder::default();
+        let decoded: String = text.chunks(2).map(|c| decoder.decode(c)).collect();
+        assert_eq!(decoded, "日志 ok");
+        assert_eq!(decoder.finish(), "");
+
+        assert_eq!(decoder.decode(&[b'a', 0xe6, 0x97]), "a");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+        assert_eq!(decoder.decode(&[0xff, b'b']), "\u{fffd}b");
+    }
+}
@@ -1,2 +1,3 @@
-mod error;
+pub mod error;
+#[allow(clippy::module_inception)]
 pub mod rotater;
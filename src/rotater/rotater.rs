@@ -1,17 +1,23 @@
 use std::{
     ffi::OsStr,
+    os::unix::fs::{chown, MetadataExt},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
 use anyhow::{anyhow, Context, Ok, Result};
 use chrono::{DateTime, Days, TimeZone, Utc};
-use dashmap::DashSet;
+use dashmap::{DashMap, DashSet};
 use log::{error, info};
 use tokio::{
     fs::File,
     io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
-    sync::mpsc,
+    sync::{broadcast, mpsc, watch},
+    task::JoinSet,
 };
 
 use crate::config::config::Log;
@@ -22,6 +28,47 @@ pub struct Rotater {
     // if recv none, finish
     signal_rotate_recv: mpsc::Receiver<Log>,
     signal_rotate_send: mpsc::Sender<Log>,
+    // path of log is sent once it is rotated
+    rotated: broadcast::Sender<String>,
+    stats: Arc<DashMap<String, Arc<LogStats>>>,
+}
+
+/// counters of a log path kept for metrics
+#[derive(Debug, Default)]
+pub struct LogStats {
+    pub written_bytes: AtomicU64,
+    pub rotations: AtomicU64,
+    pub compressions: AtomicU64,
+    pub compress_micros: AtomicU64,
+    // size of rotated logs minus size of their gzip
+    pub compress_saved_bytes: AtomicU64,
+    pub backups_deleted: AtomicU64,
+}
+
+// handle held by log writers to send rotate tasks to the running rotater
+#[derive(Clone)]
+pub struct RotateHandle {
+    signal_rotate_send: mpsc::Sender<Log>,
+    rotated: broadcast::Sender<String>,
+    stats: Arc<DashMap<String, Arc<LogStats>>>,
+}
+
+impl RotateHandle {
+    pub async fn add_rotate_task(&self, conf: Log) {
+        if let Err(e) = self.signal_rotate_send.send(conf).await {
+            error!("add rotate task failed: {}", e)
+        }
+    }
+
+    /// receive paths of logs rotated from now on
+    pub fn subscribe_rotated(&self) -> broadcast::Receiver<String> {
+        self.rotated.subscribe()
+    }
+
+    /// stats of log path, created on first use
+    pub fn stats(&self, path: &str) -> Arc<LogStats> {
+        self.stats.entry(path.to_string()).or_default().clone()
+    }
 }
 
 // rotater is singleton
@@ -32,11 +79,21 @@ impl Rotater {
         let s = Self {
             signal_rotate_send: send,
             signal_rotate_recv: recv,
+            rotated: broadcast::channel(channel_length).0,
+            stats: Arc::new(DashMap::new()),
         };
 
         Ok(s)
     }
 
+    pub fn handle(&self) -> RotateHandle {
+        RotateHandle {
+            signal_rotate_send: self.signal_rotate_send.clone(),
+            rotated: self.rotated.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+
     // send log conf to backend rotater
     pub async fn add_rotate_task(&self, conf: Log) {
         if let Err(e) = self.signal_rotate_send.send(conf).await {
@@ -44,40 +101,59 @@ impl Rotater {
         }
     }
 
-    pub async fn run(&mut self) {
+    /// run until shutdown is signaled, rotations already in flight are
+    /// finished before return
+    pub async fn run(&mut self, mut shutdown: watch::Receiver<bool>) {
         let running_path = Arc::new(DashSet::<String>::new());
+        let mut tasks = JoinSet::new();
 
         loop {
-            let received_log = match self.signal_rotate_recv.recv().await {
-                Some(r) => r,
-                None => return,
+            let received_log = tokio::select! {
+                r = self.signal_rotate_recv.recv() => match r {
+                    Some(r) => r,
+                    None => break,
+                },
+                Some(_) = tasks.join_next(), if !tasks.is_empty() => continue,
+                _ = shutdown.changed() => break,
             };
 
-            if running_path.contains(received_log.path.as_str()) {
+            if !running_path.insert(received_log.path.clone()) {
                 continue;
             }
             // rotate time
             let running_path = running_path.clone();
-            tokio::spawn(async move {
-                running_path.insert(received_log.path.as_str().to_string());
-                if let Err(e) = Self::rotate(&received_log).await {
+            let rotated = self.rotated.clone();
+            let stats = self.handle().stats(&received_log.path);
+            tasks.spawn(async move {
+                if let Err(e) = Self::rotate(&received_log, &stats).await {
                     error!("rotate with conf {} failed: {}", received_log, e);
+                } else {
+                    // no subscriber is not an error
+                    let _ = rotated.send(received_log.path.clone());
                 };
                 running_path.remove(received_log.path.as_str());
             });
         }
+
+        self.signal_rotate_recv.close();
+        while tasks.join_next().await.is_some() {}
+        info!("rotater exit");
     }
 
-    async fn rotate(conf: &Log) -> Result<()> {
+    async fn rotate(conf: &Log, stats: &LogStats) -> Result<()> {
         let path = &conf.path;
 
-        tokio::fs::File::create(path).await?;
-
         let dir = Path::new(path.as_str()).parent().unwrap_or(Path::new("/"));
         let rotated_filename = Self::format_path_by_time(path, Utc::now());
         let rotated_target = dir.join(rotated_filename);
 
+        // writers reopen the path once they see it replaced, new file keeps
+        // owner of the rotated one
         tokio::fs::rename(path, &rotated_target).await?;
+        tokio::fs::File::create(path).await?;
+        let meta = tokio::fs::metadata(&rotated_target).await?;
+        chown(path, Some(meta.uid()), Some(meta.gid()))?;
+        stats.rotations.fetch_add(1, Ordering::Relaxed);
 
         info!(
             "rotated log {} to {}",
@@ -86,28 +162,44 @@ impl Rotater {
         );
 
         if conf.compress {
-            Self::gzip_from_path(rotated_target).await?;
+            let start = Instant::now();
+            let (raw, compressed) = Self::gzip_from_path(rotated_target).await?;
+            stats.compressions.fetch_add(1, Ordering::Relaxed);
+            stats
+                .compress_micros
+                .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+            stats
+                .compress_saved_bytes
+                .fetch_add(raw.saturating_sub(compressed), Ordering::Relaxed);
         }
 
-        Self::clean_extra_backups(
+        // max_days 0 keeps backups of any age
+        let deadline = match conf.max_days {
+            0 => DateTime::<Utc>::MIN_UTC,
+            d => Utc::now()
+                .checked_sub_days(Days::new(d))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC),
+        };
+        let deleted = Self::clean_extra_backups(
             dir,
-            Path::new(path).file_stem().unwrap(),
-            Utc::now()
-                .checked_sub_days(Days::new(conf.max_days))
-                .unwrap(),
+            Path::new(path).file_stem().unwrap_or_default(),
+            deadline,
             conf.max_backups,
         )
         .await
         .context("clean extra backups failed")?;
+        stats.backups_deleted.fetch_add(deleted, Ordering::Relaxed);
 
         Ok(())
     }
 
-    /// gzip file by path to file.gz and delete raw file
-    async fn gzip_from_path<P: AsRef<Path>>(path: P) -> Result<()> {
+    /// gzip file by path to file.gz and delete raw file, return size of
+    /// raw file and gzip file
+    async fn gzip_from_path<P: AsRef<Path>>(path: P) -> Result<(u64, u64)> {
         let file_input = File::open(path.as_ref())
             .await
             .context("open input file failed")?;
+        let raw = file_input.metadata().await?.len();
         let mut input = BufReader::new(file_input);
 
         let path_output = match path.as_ref().as_os_str().to_str() {
@@ -129,7 +221,8 @@ impl Rotater {
         tokio::fs::remove_file(path).await?;
 
         info!("compress file {path_output}");
-        Ok(())
+        let compressed = tokio::fs::metadata(&path_output).await?.len();
+        Ok((raw, compressed))
     }
 
     async fn gzip<W: AsyncWrite + Unpin, R: AsyncRead + Unpin>(
@@ -149,22 +242,20 @@ impl Rotater {
 
     /// deadline = current time - roatate duration
     /// origin_filename = {test}-20230317200700.log.gz
+    /// return number of deleted backups
     async fn clean_extra_backups(
         dir: &Path,
         origin_filename: &OsStr,
         deadline: DateTime<Utc>,
         max_backups: usize,
-    ) -> Result<()> {
+    ) -> Result<u64> {
+        let mut deleted = 0;
+        let prefix = format!("{}-", origin_filename.to_string_lossy());
         let mut entrys = tokio::fs::read_dir(dir).await?;
         let mut filename_vec = Vec::new();
         while let Some(entry) = entrys.next_entry().await? {
             if entry.file_type().await?.is_dir()
-                || entry
-                    .file_name()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-                    .starts_with(origin_filename.to_str().unwrap())
+                || !entry.file_name().to_string_lossy().starts_with(&prefix)
             {
                 continue;
             }
@@ -175,36 +266,44 @@ impl Rotater {
                 tokio::fs::remove_file(dir.join(entry.file_name()))
                     .await
                     .context("remove backup file failed")?;
+                deleted += 1;
                 continue;
             }
             filename_vec.push(entry.file_name());
         }
 
         if filename_vec.len() <= max_backups {
-            return Ok(());
+            return Ok(deleted);
         }
 
-        let topk_filename = top_k(&mut filename_vec, max_backups);
+        // keep the newest max_backups files
+        let k = filename_vec.len() - max_backups + 1;
+        let topk_filename = top_k(&mut filename_vec, k).clone();
         let topk_time = Self::parse_path_to_time(topk_filename)?;
         for n in &filename_vec {
             if Self::parse_path_to_time(n).context("parse topk time failed")? < topk_time {
                 tokio::fs::remove_file(dir.join(n))
                     .await
                     .context("remove topk files failed")?;
+                deleted += 1;
             }
         }
-        Ok(())
+        Ok(deleted)
     }
 
+    /// time is between the last '-' and the first '.' after it,
+    /// both test-20230317200700.log and test-20230317200700.log.gz are valid
     fn parse_path_to_time<P: AsRef<Path>>(path: P) -> Result<DateTime<Utc>> {
-        let ts = path
+        let filename = path
             .as_ref()
-            .file_stem()
+            .file_name()
             .and_then(OsStr::to_str)
-            .unwrap()
-            .split('-')
-            .last()
-            .unwrap();
+            .ok_or_else(|| anyhow!("invalid backup path {:?}", path.as_ref()))?;
+        let ts = filename
+            .rsplit('-')
+            .next()
+            .and_then(|s| s.split('.').next())
+            .unwrap_or_default();
         Utc.datetime_from_str(ts, TIME_FORMAT)
             .context("convert str to time failed")
     }
@@ -241,11 +340,11 @@ fn quick_select<T: PartialOrd>(v: &mut Vec<T>, left: usize, right: usize, k: usi
         if i != left {
             i += 1;
         }
-        while &v[i] < &v[mid_index] {
+        while v[i] < v[mid_index] {
             i += 1;
         }
         j -= 1;
-        while &v[j] > &v[mid_index] {
+        while v[j] > v[mid_index] {
             j -= 1;
         }
         if i < j {
@@ -274,7 +373,7 @@ mod tests {
 
     #[tokio::test]
     async fn async_gzip_test() {
-        let mut input = Cursor::new(['1' as u8; 10]);
+        let mut input = Cursor::new([b'1'; 10]);
         let mut output = Cursor::new(Vec::with_capacity(10));
         Rotater::gzip(&mut input, &mut output).await.unwrap();
 
@@ -289,13 +388,50 @@ mod tests {
 
     #[tokio::test]
     async fn async_parse_and_format_time_test() {
-        let t = Utc.with_ymd_and_hms(2023, 3, 17, 20, 07, 00).unwrap();
+        let t = Utc.with_ymd_and_hms(2023, 3, 17, 20, 7, 0).unwrap();
         let path = Rotater::format_path_by_time("test.log", t);
 
         assert_eq!("test-20230317200700.log", path.to_str().unwrap());
         assert_eq!(Rotater::parse_path_to_time(path.as_path()).unwrap(), t);
     }
 
+    #[tokio::test]
+    async fn async_clean_extra_backups_test() {
+        let dir = std::env::temp_dir().join(format!("sup-rotater-clean-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "run.log",
+            "run-20230317200700.log.gz",
+            "run-20230318200700.log",
+            "run-20230319200700.log.gz",
+            "other-20230317200700.log",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let deleted =
+            Rotater::clean_extra_backups(&dir, OsStr::new("run"), DateTime::<Utc>::MIN_UTC, 2)
+                .await
+                .unwrap();
+        assert_eq!(deleted, 1);
+
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            vec![
+                "other-20230317200700.log",
+                "run-20230318200700.log",
+                "run-20230319200700.log.gz",
+                "run.log"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[test]
     fn quick_select_test() {
         let mut v = vec![1, 4, 8, 3, 2, 5];